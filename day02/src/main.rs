@@ -1,8 +1,10 @@
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::{
-    io::{self, Read},
+    env, fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
     str::FromStr,
 };
 
@@ -12,26 +14,33 @@ fn main() -> Result<()> {
 
     let commands = parse_commands(&input)?;
 
-    part1(&commands);
-    part2(&commands);
+    let simple = trajectory(&commands, Steering::Simple)?;
+    let aimed = trajectory(&commands, Steering::Aimed)?;
+
+    part1(&simple)?;
+    part2(&aimed)?;
+
+    // Optionally export the aimed dive profile for plotting.
+    if let Some(path) = env::args().nth(1) {
+        let file = File::create(&path).with_context(|| format!("cannot create {}", path))?;
+        write_csv(&aimed, BufWriter::new(file))?;
+    }
 
     Ok(())
 }
 
-fn part1(commands: &[Command]) {
-    let end = commands
-        .iter()
-        .fold(Position::origin(), |pos, c| c.run_simple(pos));
+fn part1(path: &[Position]) -> Result<()> {
+    let end = path.last().context("empty trajectory")?;
+    println!("Part 1 answer: {}", end.product()?);
 
-    println!("Part 1 answer: {}", end.x * end.y);
+    Ok(())
 }
 
-fn part2(commands: &[Command]) {
-    let end = commands
-        .iter()
-        .fold(Position::origin(), |pos, c| c.run_aimed(pos));
+fn part2(path: &[Position]) -> Result<()> {
+    let end = path.last().context("empty trajectory")?;
+    println!("Part 2 answer: {}", end.product()?);
 
-    println!("Part 2 answer: {}", end.x * end.y);
+    Ok(())
 }
 
 #[derive(Clone, Copy)]
@@ -49,6 +58,18 @@ impl Position {
     fn origin() -> Self {
         Self::new(0, 0, 0)
     }
+
+    fn product(&self) -> Result<i32> {
+        self.x
+            .checked_mul(self.y)
+            .ok_or_else(|| anyhow!("arithmetic overflow multiplying {} by {}", self.x, self.y))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Steering {
+    Simple,
+    Aimed,
 }
 
 enum Command {
@@ -58,19 +79,40 @@ enum Command {
 }
 
 impl Command {
-    fn run_simple(&self, pos: Position) -> Position {
-        match self {
-            Command::Up(n) => Position::new(pos.x, pos.y - n, pos.aim),
-            Command::Down(n) => Position::new(pos.x, pos.y + n, pos.aim),
-            Command::Forward(n) => Position::new(pos.x + n, pos.y, pos.aim),
+    fn run(&self, pos: Position, steering: Steering) -> Option<Position> {
+        match steering {
+            Steering::Simple => self.run_simple(pos),
+            Steering::Aimed => self.run_aimed(pos),
         }
     }
 
-    fn run_aimed(&self, pos: Position) -> Position {
+    fn run_simple(&self, pos: Position) -> Option<Position> {
+        Some(match self {
+            Command::Up(n) => Position::new(pos.x, pos.y.checked_sub(*n)?, pos.aim),
+            Command::Down(n) => Position::new(pos.x, pos.y.checked_add(*n)?, pos.aim),
+            Command::Forward(n) => Position::new(pos.x.checked_add(*n)?, pos.y, pos.aim),
+        })
+    }
+
+    fn run_aimed(&self, pos: Position) -> Option<Position> {
+        Some(match self {
+            Command::Up(n) => Position::new(pos.x, pos.y, pos.aim.checked_sub(*n)?),
+            Command::Down(n) => Position::new(pos.x, pos.y, pos.aim.checked_add(*n)?),
+            Command::Forward(n) => Position::new(
+                pos.x.checked_add(*n)?,
+                pos.y.checked_add(pos.aim.checked_mul(*n)?)?,
+                pos.aim,
+            ),
+        })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Command::Up(n) => Position::new(pos.x, pos.y, pos.aim - n),
-            Command::Down(n) => Position::new(pos.x, pos.y, pos.aim + n),
-            Command::Forward(n) => Position::new(pos.x + n, pos.y + pos.aim * n, pos.aim),
+            Command::Up(n) => write!(f, "up {}", n),
+            Command::Down(n) => write!(f, "down {}", n),
+            Command::Forward(n) => write!(f, "forward {}", n),
         }
     }
 }
@@ -101,3 +143,37 @@ impl FromStr for Command {
 fn parse_commands(input: &str) -> Result<Vec<Command>> {
     input.lines().map(|line| line.parse()).collect()
 }
+
+// Returns every position visited, starting with the origin, so the result always holds
+// one more position than there are commands.
+fn trajectory(commands: &[Command], steering: Steering) -> Result<Vec<Position>> {
+    let mut pos = Position::origin();
+    let mut path = Vec::with_capacity(commands.len() + 1);
+    path.push(pos);
+
+    for (step, command) in commands.iter().enumerate() {
+        pos = command.run(pos, steering).ok_or_else(|| {
+            anyhow!(
+                "arithmetic overflow at step {} ({}) from x={}, y={}, aim={}",
+                step + 1,
+                command,
+                pos.x,
+                pos.y,
+                pos.aim
+            )
+        })?;
+        path.push(pos);
+    }
+
+    Ok(path)
+}
+
+fn write_csv<W: Write>(path: &[Position], mut w: W) -> Result<()> {
+    writeln!(w, "step,x,y,aim")?;
+    for (step, pos) in path.iter().enumerate() {
+        writeln!(w, "{},{},{},{}", step, pos.x, pos.y, pos.aim)?;
+    }
+    w.flush()?;
+
+    Ok(())
+}
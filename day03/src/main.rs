@@ -1,6 +1,12 @@
-use std::io::{self, Read};
+use std::{
+    env, fmt,
+    io::{self, Read},
+    ops::Range,
+    str::FromStr,
+};
 
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
+use num_bigint::BigUint;
 
 fn main() -> Result<()> {
     let mut input = String::new();
@@ -9,107 +15,386 @@ fn main() -> Result<()> {
     let report = read_report(&input)?;
 
     part1(&report);
-    part2(&report);
+    part2(&report)?;
+
+    // Optional arguments: "--trace" prints the elimination steps for each rating and
+    // "--criteria <most|least>:<ones|zeroes|error>" reduces using custom bit criteria.
+    let mut show_trace = false;
+    let mut criteria = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => show_trace = true,
+            "--criteria" => criteria.push(args.next().context("missing criteria")?.parse()?),
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    if show_trace {
+        for (name, c) in [("Oxygen", &BitCriteria::OXYGEN), ("CO2", &BitCriteria::CO2)] {
+            println!("{} rating ({}):", name, c);
+            report.trace(c)?.print(&report);
+        }
+    }
+
+    for c in &criteria {
+        println!("Custom rating ({}): {}", c, report.reduce(c)?.value());
+        if show_trace {
+            report.trace(c)?.print(&report);
+        }
+    }
 
     Ok(())
 }
 
 fn part1(report: &Report) {
     let (gamma, epsilon) = report.gamma_epsilson();
-    println!("Part 1 answer: {}", gamma * epsilon);
+    println!("Part 1 answer: {}", gamma.value() * epsilon.value());
+}
+
+fn part2(report: &Report) -> Result<()> {
+    let (oxygen, co2) = report.oxygen_co2()?;
+    println!("Part 2 answer: {}", oxygen.value() * co2.value());
+
+    Ok(())
 }
 
-fn part2(report: &Report) {
-    let (oxygen, co2) = report.oxygen_co2();
-    println!("Part 2 answer: {}", oxygen * co2);
+const WORD_BITS: usize = u64::BITS as usize;
+
+// A fixed-width bit string packed most significant bit first, so that the derived
+// ordering matches the numeric ordering of samples of the same width.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Sample {
+    words: Vec<u64>,
+    width: usize,
 }
 
-type Sample = u32;
+impl Sample {
+    fn zeroes(width: usize) -> Self {
+        Sample {
+            words: vec![0; width.div_ceil(WORD_BITS)],
+            width,
+        }
+    }
 
+    fn mask(i: usize) -> u64 {
+        1 << (WORD_BITS - 1 - i % WORD_BITS)
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.words[i / WORD_BITS] & Self::mask(i) != 0
+    }
+
+    fn set(&mut self, i: usize, value: bool) {
+        if value {
+            self.words[i / WORD_BITS] |= Self::mask(i);
+        } else {
+            self.words[i / WORD_BITS] &= !Self::mask(i);
+        }
+    }
+
+    fn invert(&self) -> Self {
+        let mut inverted = Sample {
+            words: self.words.iter().map(|w| !w).collect(),
+            width: self.width,
+        };
+
+        // Keep the padding bits after the last sample bit cleared.
+        let padding = inverted.words.len() * WORD_BITS - self.width;
+        if let Some(last) = inverted.words.last_mut() {
+            *last &= u64::MAX.checked_shl(padding as u32).unwrap_or(0);
+        }
+
+        inverted
+    }
+
+    fn value(&self) -> BigUint {
+        (0..self.width).fold(BigUint::default(), |acc, i| {
+            (acc << 1u32) + BigUint::from(self.bit(i) as u8)
+        })
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.width {
+            write!(f, "{}", self.bit(i) as u8)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct BitCount {
+    ones: usize,
+    zeroes: usize,
+}
+
+// The samples are kept sorted so that the samples sharing any given prefix form a
+// contiguous run, which lets reductions narrow a range rather than copy samples.
 struct Report {
     samples: Vec<Sample>,
     width: usize,
 }
 
 impl Report {
-    fn gamma_epsilson(&self) -> (Sample, Sample) {
-        let mut gamma = 0;
-        for bit in 0..self.width {
-            let mask = 1 << (self.width - bit - 1);
-            let ones = self
-                .samples
-                .iter()
-                .fold(0, |acc, s| if s & mask > 0 { acc + 1 } else { acc });
+    fn new(mut samples: Vec<Sample>, width: usize) -> Self {
+        samples.sort_unstable();
+        Report { samples, width }
+    }
+
+    // Counts the ones and zeroes at every bit position in a single pass over the samples.
+    fn bit_counts(&self) -> Vec<BitCount> {
+        let mut ones = vec![0; self.width];
+        for sample in &self.samples {
+            for (w, &word) in sample.words.iter().enumerate() {
+                let mut word = word;
+                while word != 0 {
+                    let offset = word.leading_zeros() as usize;
+                    ones[w * WORD_BITS + offset] += 1;
+                    word &= !(1 << (WORD_BITS - 1 - offset));
+                }
+            }
+        }
+
+        ones.into_iter()
+            .map(|ones| BitCount {
+                ones,
+                zeroes: self.samples.len() - ones,
+            })
+            .collect()
+    }
 
-            if ones > self.samples.len() / 2 {
-                gamma |= mask;
+    fn gamma_epsilson(&self) -> (Sample, Sample) {
+        let mut gamma = Sample::zeroes(self.width);
+        for (bit, count) in self.bit_counts().iter().enumerate() {
+            if count.ones > count.zeroes {
+                gamma.set(bit, true);
             }
         }
 
-        let epsilon = !gamma & !(Sample::MAX << self.width);
+        let epsilon = gamma.invert();
 
         (gamma, epsilon)
     }
 
-    fn oxygen_co2(&self) -> (Sample, Sample) {
-        (self.reduce(true), self.reduce(false))
+    fn oxygen_co2(&self) -> Result<(Sample, Sample)> {
+        Ok((
+            self.reduce(&BitCriteria::OXYGEN)?,
+            self.reduce(&BitCriteria::CO2)?,
+        ))
+    }
+
+    fn reduce(&self, criteria: &BitCriteria) -> Result<Sample> {
+        let trace = self.trace(criteria)?;
+
+        // Samples that survive every bit are identical so any of them will do.
+        Ok(self.samples[trace.survivors().start].clone())
     }
 
-    fn reduce(&self, oxygen: bool) -> Sample {
-        let mut active = self.samples.clone();
+    fn trace(&self, criteria: &BitCriteria) -> Result<Trace> {
+        let mut trace = Trace {
+            initial: 0..self.samples.len(),
+            steps: vec![],
+        };
+
+        let mut active = trace.initial.clone();
         for bit in 0..self.width {
             if active.len() == 1 {
                 break;
             }
 
-            let mask = 1 << (self.width - bit - 1);
+            // Every active sample shares the same prefix up to this bit, so the ones
+            // with a zero here sort before the ones with a one.
+            let split =
+                active.start + self.samples[active.clone()].partition_point(|s| !s.bit(bit));
 
-            let mut ones = vec![];
-            let mut zeroes = vec![];
-            for sample in active {
-                if sample & mask > 0 {
-                    ones.push(sample);
-                } else {
-                    zeroes.push(sample);
-                }
-            }
+            let (ones, zeroes) = (active.end - split, split - active.start);
+            let keep = criteria
+                .select(ones, zeroes)
+                .with_context(|| format!("cannot apply {} criteria at bit {}", criteria, bit))?;
+
+            active = if keep {
+                split..active.end
+            } else {
+                active.start..split
+            };
+
+            trace.steps.push(TraceStep {
+                bit,
+                kept: keep,
+                survivors: active.clone(),
+            });
+        }
+
+        Ok(trace)
+    }
+}
+
+// The surviving samples, as ranges of the sorted report samples, after each bit has
+// been considered by a reduction.
+struct Trace {
+    initial: Range<usize>,
+    steps: Vec<TraceStep>,
+}
 
-            active = match (ones.len(), zeroes.len(), oxygen) {
-                // Oxygen criteria:
-                (i, j, true) if i == j => ones,
-                (i, j, true) if i > j => ones,
-                (i, j, true) if i < j => zeroes,
+impl Trace {
+    fn survivors(&self) -> Range<usize> {
+        self.steps
+            .last()
+            .map_or(&self.initial, |step| &step.survivors)
+            .clone()
+    }
 
-                // CO2 criteria:
-                (i, j, false) if i == j => zeroes,
-                (i, j, false) if i > j => zeroes,
-                (i, j, false) if i < j => ones,
+    fn print(&self, report: &Report) {
+        for step in &self.steps {
+            let survivors = report.samples[step.survivors.clone()]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>();
+            println!(
+                "  bit {}: kept {}s, {} survivor(s): {}",
+                step.bit,
+                step.kept as u8,
+                survivors.len(),
+                survivors.join(" ")
+            );
+        }
 
-                // Rust can't figure out that the match criteria above is complete.
-                _ => panic!("algorithm error"),
+        for sample in &report.samples[self.survivors()] {
+            println!("  result: {}", sample);
+        }
+    }
+}
+
+struct TraceStep {
+    bit: usize,
+    kept: bool,
+    survivors: Range<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Selection {
+    MostCommon,
+    LeastCommon,
+}
+
+#[derive(Clone, Copy)]
+enum TieBreak {
+    Ones,
+    Zeroes,
+    Error,
+}
+
+struct BitCriteria {
+    selection: Selection,
+    tie_break: TieBreak,
+}
+
+impl BitCriteria {
+    const OXYGEN: BitCriteria = BitCriteria::new(Selection::MostCommon, TieBreak::Ones);
+    const CO2: BitCriteria = BitCriteria::new(Selection::LeastCommon, TieBreak::Zeroes);
+
+    const fn new(selection: Selection, tie_break: TieBreak) -> Self {
+        BitCriteria {
+            selection,
+            tie_break,
+        }
+    }
+
+    // Returns the bit value whose samples should be kept given the number of remaining
+    // samples with each value. A value that no sample has is never selected.
+    fn select(&self, ones: usize, zeroes: usize) -> Result<bool> {
+        if ones == 0 || zeroes == 0 {
+            return Ok(ones > 0);
+        }
+
+        if ones == zeroes {
+            return match self.tie_break {
+                TieBreak::Ones => Ok(true),
+                TieBreak::Zeroes => Ok(false),
+                TieBreak::Error => bail!("tie between {} ones and {} zeroes", ones, zeroes),
             };
         }
 
-        // The algorithm guarantees that as long as we started with at least a single
-        // sample, one sample will remain.
-        assert_eq!(active.len(), 1);
-        *active.first().unwrap()
+        Ok(match self.selection {
+            Selection::MostCommon => ones > zeroes,
+            Selection::LeastCommon => ones < zeroes,
+        })
+    }
+}
+
+impl FromStr for BitCriteria {
+    type Err = anyhow::Error;
+
+    // Parses criteria of the form "<most|least>:<ones|zeroes|error>".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (selection, tie_break) = s
+            .split_once(':')
+            .with_context(|| format!("invalid bit criteria: {}", s))?;
+
+        let selection = match selection {
+            "most" => Selection::MostCommon,
+            "least" => Selection::LeastCommon,
+            _ => bail!("invalid bit selection: {}", selection),
+        };
+
+        let tie_break = match tie_break {
+            "ones" => TieBreak::Ones,
+            "zeroes" => TieBreak::Zeroes,
+            "error" => TieBreak::Error,
+            _ => bail!("invalid tie-break: {}", tie_break),
+        };
+
+        Ok(BitCriteria::new(selection, tie_break))
+    }
+}
+
+impl fmt::Display for BitCriteria {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let selection = match self.selection {
+            Selection::MostCommon => "most",
+            Selection::LeastCommon => "least",
+        };
+
+        let tie_break = match self.tie_break {
+            TieBreak::Ones => "ones",
+            TieBreak::Zeroes => "zeroes",
+            TieBreak::Error => "error",
+        };
+
+        write!(f, "{}:{}", selection, tie_break)
     }
 }
 
 fn read_report(input: &str) -> Result<Report> {
-    let mut width = 0;
+    let mut width = None;
     let mut samples = vec![];
 
-    for line in input.lines() {
-        width = width.max(line.len());
+    for (i, line) in input.lines().enumerate() {
+        let expected = *width.get_or_insert(line.len());
         ensure!(
-            width <= Sample::BITS as usize,
-            "sample size bigger than expected"
+            line.len() == expected,
+            "line {}: sample has width {} but expected {}",
+            i + 1,
+            line.len(),
+            expected
         );
 
-        samples.push(Sample::from_str_radix(line, 2)?);
+        let mut sample = Sample::zeroes(expected);
+        for (bit, c) in line.chars().enumerate() {
+            match c {
+                '0' => {}
+                '1' => sample.set(bit, true),
+                _ => bail!("line {}: invalid bit '{}'", i + 1, c),
+            }
+        }
+
+        samples.push(sample);
     }
 
-    Ok(Report { samples, width })
+    ensure!(!samples.is_empty(), "report has no samples");
+
+    Ok(Report::new(samples, width.unwrap_or_default()))
 }
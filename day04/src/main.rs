@@ -1,10 +1,13 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    env, fmt,
     io::{self, Read},
     str::FromStr,
 };
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rayon::prelude::*;
 
 fn main() -> Result<()> {
     let mut input = String::new();
@@ -12,69 +15,269 @@ fn main() -> Result<()> {
 
     let mut game: BingoGame = input.parse()?;
 
-    part1(&mut game.clone());
-    part2(&mut game);
+    // Optional "--win <pattern>" arguments select the winning patterns; by default a
+    // card wins with a full row or column. "--timeline" prints every card's win and
+    // "--simulate <trials>" (with an optional "--seed <n>") estimates each card's odds
+    // of winning first and last over random draw orders.
+    let mut patterns = vec![];
+    let mut show_timeline = false;
+    let mut trials = None;
+    let mut seed = 0;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeline" => show_timeline = true,
+            "--win" => patterns.push(args.next().context("missing win pattern")?.parse()?),
+            "--simulate" => trials = Some(args.next().context("missing trials")?.parse()?),
+            "--seed" => seed = args.next().context("missing seed")?.parse()?,
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    if !patterns.is_empty() {
+        game.set_patterns(&patterns)?;
+    }
+
+    let timeline = game.timeline();
+
+    part1(&timeline);
+    part2(&timeline);
+
+    if show_timeline {
+        for win in &timeline {
+            println!(
+                "Draw {} (number {}): card {} wins with score {}",
+                win.draw + 1,
+                win.num,
+                win.card + 1,
+                win.score
+            );
+        }
+    }
+
+    if let Some(trials) = trials {
+        for (i, odds) in game.simulate(trials, seed).iter().enumerate() {
+            println!(
+                "Card {}: wins first {}, wins last {}",
+                i + 1,
+                odds.first,
+                odds.last
+            );
+        }
+    }
 
     Ok(())
 }
 
-fn part1(game: &mut BingoGame) {
-    match game.play_first_winner() {
-        Some(score) => println!("Part 1 answer: {}", score),
+fn part1(timeline: &[BingoWin]) {
+    match timeline.first() {
+        Some(win) => println!("Part 1 answer: {}", win.score),
         _ => println!("Part 1: no winner found"),
     }
 }
 
-fn part2(game: &mut BingoGame) {
-    match game.play_last_winner() {
-        Some(score) => println!("Part 2 answer: {}", score),
+fn part2(timeline: &[BingoWin]) {
+    match timeline.last() {
+        Some(win) => println!("Part 2 answer: {}", win.score),
         _ => println!("Part 2: no winner found"),
     }
 }
 
-const BINGO_SIZE: usize = 5;
-
 type Num = u32;
 
-#[derive(Clone)]
 struct BingoGame {
     sequence: Vec<Num>,
     cards: Vec<BingoCard>,
 }
 
 impl BingoGame {
-    fn play_first_winner(&mut self) -> Option<Num> {
-        for num in &self.sequence {
-            for card in &mut self.cards {
-                if let BingoResult::Win(score) = card.play(*num) {
-                    return Some(score);
-                }
-            }
+    fn set_patterns(&mut self, patterns: &[WinPattern]) -> Result<()> {
+        for (i, card) in self.cards.iter_mut().enumerate() {
+            card.set_patterns(patterns)
+                .with_context(|| format!("card {}", i + 1))?;
         }
 
-        None
+        Ok(())
     }
 
-    fn play_last_winner(&mut self) -> Option<Num> {
-        let mut last_score = None;
-        let mut winning_cards = HashSet::new();
-        for num in &self.sequence {
-            for (i, card) in self.cards.iter_mut().enumerate() {
-                if winning_cards.contains(&i) {
-                    continue;
-                }
+    // Plays the whole sequence and returns every card's win in the order the wins
+    // happen.
+    fn timeline(&self) -> Vec<BingoWin> {
+        self.play(&self.index(), &self.sequence)
+    }
+
+    // Maps each number to the (card, cell) pairs that hold it.
+    fn index(&self) -> HashMap<Num, Vec<(usize, usize)>> {
+        let mut index: HashMap<Num, Vec<(usize, usize)>> = HashMap::new();
+        for (i, card) in self.cards.iter().enumerate() {
+            for (cell, &num) in card.values.iter().enumerate() {
+                index.entry(num).or_default().push((i, cell));
+            }
+        }
+
+        index
+    }
 
-                if let BingoResult::Win(score) = card.play(*num) {
-                    last_score = Some(score);
-                    winning_cards.insert(i);
+    // Each drawn number only visits the cells that hold it, and each card counts down
+    // the unmarked cells of its win lines, so the cost is proportional to the number of
+    // marks rather than the number of cards times the sequence length.
+    fn play(&self, index: &HashMap<Num, Vec<(usize, usize)>>, sequence: &[Num]) -> Vec<BingoWin> {
+        let mut states = self.cards.iter().map(CardState::new).collect::<Vec<_>>();
+        let mut timeline = vec![];
+        for (draw, &num) in sequence.iter().enumerate() {
+            for &(i, cell) in index.get(&num).into_iter().flatten() {
+                let state = &mut states[i];
+                if !state.won && state.mark(&self.cards[i], cell) {
+                    timeline.push(BingoWin {
+                        draw,
+                        num,
+                        card: i,
+                        score: num * state.unmarked,
+                    });
                 }
             }
         }
 
-        last_score
+        timeline
+    }
+
+    // Plays the given number of random permutations of the sequence and estimates how
+    // likely each card is to win first and to win last. When several cards win on the
+    // first (or last) winning draw of a trial, each gets an equal share of the win, so
+    // the odds of the cards add up to one. Each
+    // trial has its own generator keyed by the base seed and its trial number, which keeps
+    // the results reproducible however the trials are spread across threads.
+    fn simulate(&self, trials: u64, seed: u64) -> Vec<CardOdds> {
+        let index = self.index();
+        let tally = (0..trials)
+            .into_par_iter()
+            .fold(
+                || Tally::new(self.cards.len()),
+                |mut tally, trial| {
+                    let mut sequence = self.sequence.clone();
+                    sequence.shuffle(&mut trial_rng(seed, trial));
+
+                    let timeline = self.play(&index, &sequence);
+                    if let (Some(first), Some(last)) = (timeline.first(), timeline.last()) {
+                        let firsts = timeline.iter().take_while(|w| w.draw == first.draw);
+                        Tally::share(&mut tally.first, firsts);
+                        let lasts = timeline.iter().rev().take_while(|w| w.draw == last.draw);
+                        Tally::share(&mut tally.last, lasts);
+                    }
+
+                    tally
+                },
+            )
+            .reduce(|| Tally::new(self.cards.len()), Tally::merge);
+
+        tally
+            .first
+            .iter()
+            .zip(&tally.last)
+            .map(|(&first, &last)| CardOdds {
+                first: Estimate::new(first, trials),
+                last: Estimate::new(last, trials),
+            })
+            .collect()
+    }
+}
+
+// A generator for one trial of a simulation. The base seed and the trial number each fill
+// half of the generator's key, so no two (seed, trial) pairs share a stream and runs with
+// neighbouring seeds are independent.
+fn trial_rng(seed: u64, trial: u64) -> StdRng {
+    let mut key = <StdRng as SeedableRng>::Seed::default();
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&trial.to_le_bytes());
+
+    StdRng::from_seed(key)
+}
+
+// The number of trials in which each card won first and last, where a card that tied
+// with others counts for its share of the win.
+struct Tally {
+    first: Vec<f64>,
+    last: Vec<f64>,
+}
+
+impl Tally {
+    fn new(cards: usize) -> Self {
+        Tally {
+            first: vec![0.0; cards],
+            last: vec![0.0; cards],
+        }
+    }
+
+    fn share<'a>(counts: &mut [f64], wins: impl Iterator<Item = &'a BingoWin> + Clone) {
+        let share = 1.0 / wins.clone().count() as f64;
+        for win in wins {
+            counts[win.card] += share;
+        }
+    }
+
+    fn merge(mut self, other: Tally) -> Self {
+        for (a, b) in self.first.iter_mut().zip(other.first) {
+            *a += b;
+        }
+        for (a, b) in self.last.iter_mut().zip(other.last) {
+            *a += b;
+        }
+
+        self
+    }
+}
+
+struct CardOdds {
+    first: Estimate,
+    last: Estimate,
+}
+
+// An empirical probability with its 95% Wilson score interval.
+struct Estimate {
+    p: f64,
+    low: f64,
+    high: f64,
+}
+
+impl Estimate {
+    fn new(successes: f64, trials: u64) -> Self {
+        const Z: f64 = 1.96;
+
+        if trials == 0 {
+            return Estimate {
+                p: 0.0,
+                low: 0.0,
+                high: 1.0,
+            };
+        }
+
+        let n = trials as f64;
+        let p = successes / n;
+        let denom = 1.0 + Z * Z / n;
+        let centre = (p + Z * Z / (2.0 * n)) / denom;
+        let margin = Z * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt() / denom;
+
+        Estimate {
+            p,
+            low: (centre - margin).max(0.0),
+            high: (centre + margin).min(1.0),
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.4} [{:.4}, {:.4}]", self.p, self.low, self.high)
     }
 }
 
+struct BingoWin {
+    draw: usize,
+    num: Num,
+    card: usize,
+    score: Num,
+}
+
 impl FromStr for BingoGame {
     type Err = anyhow::Error;
 
@@ -88,57 +291,130 @@ impl FromStr for BingoGame {
 
         let cards = tail
             .split_terminator("\n\n")
-            .map(|v| v.parse().context("bad bingo card"))
+            .enumerate()
+            .map(|(i, v)| {
+                v.parse()
+                    .with_context(|| format!("bad bingo card {}", i + 1))
+            })
             .collect::<Result<Vec<_>>>()?;
 
-        Ok(BingoGame { sequence, cards })
+        let game = BingoGame { sequence, cards };
+        game.validate()?;
+
+        Ok(game)
     }
 }
 
-#[derive(Clone)]
-struct BingoCard {
-    grid: [[BingoValue; BINGO_SIZE]; BINGO_SIZE],
-}
+impl BingoGame {
+    // Checks that the draws never repeat and that every card has the same dimensions as
+    // most of the cards (the largest of them on a tie), holds distinct values, and only
+    // holds values that can be drawn. All of the problems found are reported together.
+    fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+
+        let mut drawn = HashMap::new();
+        for (draw, &num) in self.sequence.iter().enumerate() {
+            if let Some(first) = drawn.insert(num, draw) {
+                problems.push(format!(
+                    "sequence: number {} is drawn at positions {} and {}",
+                    num,
+                    first + 1,
+                    draw + 1
+                ));
+            }
+        }
 
-impl BingoCard {
-    fn new() -> Self {
-        BingoCard {
-            grid: [[BingoValue::new(0); BINGO_SIZE]; BINGO_SIZE],
+        let mut sizes = HashMap::new();
+        for card in &self.cards {
+            *sizes.entry((card.width, card.height)).or_insert(0) += 1;
         }
-    }
+        let expected = sizes
+            .into_iter()
+            .max_by_key(|&((width, height), count)| (count, width * height, width))
+            .map(|(size, _)| size);
+
+        if let Some((width, height)) = expected {
+            for (i, card) in self.cards.iter().enumerate() {
+                if (card.width, card.height) != (width, height) {
+                    problems.push(format!(
+                        "card {}: incomplete card of {}x{} cells, expected {}x{}",
+                        i + 1,
+                        card.width,
+                        card.height,
+                        width,
+                        height
+                    ));
+                }
 
-    fn play(&mut self, num: Num) -> BingoResult {
-        for row in &mut self.grid {
-            for val in row {
-                if val.num == num {
-                    val.marked = true;
+                let mut seen = HashSet::new();
+                for &num in &card.values {
+                    if !seen.insert(num) {
+                        problems.push(format!("card {}: duplicate value {}", i + 1, num));
+                    } else if !drawn.contains_key(&num) {
+                        problems.push(format!(
+                            "card {}: value {} never appears in the sequence",
+                            i + 1,
+                            num
+                        ));
+                    }
                 }
             }
         }
 
-        self.result(num)
+        ensure!(
+            problems.is_empty(),
+            "invalid bingo game:\n  {}",
+            problems.join("\n  ")
+        );
+
+        Ok(())
     }
+}
 
-    fn result(&self, last_played: Num) -> BingoResult {
-        let mut unmarked = 0;
-        let mut col_wins = [true; BINGO_SIZE];
-        let mut row_wins = [true; BINGO_SIZE];
+// A card's values are stored row by row. Each win line holds the indices of the cells
+// that must all be marked for the card to win, and each cell lists the win lines that
+// contain it.
+struct BingoCard {
+    width: usize,
+    height: usize,
+    values: Vec<Num>,
+    win_lines: Vec<Vec<usize>>,
+    cell_lines: Vec<Vec<usize>>,
+}
 
-        for (y, row) in self.grid.iter().enumerate() {
-            for (x, val) in row.iter().enumerate() {
-                if !val.marked {
-                    unmarked += val.num;
-                    col_wins[x] = false;
-                    row_wins[y] = false;
-                }
-            }
+impl BingoCard {
+    fn new(width: usize, height: usize, values: Vec<Num>) -> Self {
+        let mut card = BingoCard {
+            width,
+            height,
+            values,
+            win_lines: vec![],
+            cell_lines: vec![],
+        };
+
+        card.set_patterns(&[WinPattern::Rows, WinPattern::Columns])
+            .unwrap();
+
+        card
+    }
+
+    fn set_patterns(&mut self, patterns: &[WinPattern]) -> Result<()> {
+        let mut win_lines = vec![];
+        for pattern in patterns {
+            win_lines.extend(pattern.lines(self.width, self.height)?);
         }
 
-        if col_wins.contains(&true) || row_wins.contains(&true) {
-            BingoResult::Win(last_played * unmarked)
-        } else {
-            BingoResult::NoWin
+        let mut cell_lines = vec![vec![]; self.values.len()];
+        for (line, cells) in win_lines.iter().enumerate() {
+            for &cell in cells {
+                cell_lines[cell].push(line);
+            }
         }
+
+        self.win_lines = win_lines;
+        self.cell_lines = cell_lines;
+
+        Ok(())
     }
 }
 
@@ -146,35 +422,181 @@ impl FromStr for BingoCard {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut card = BingoCard::new();
-        for (row, line) in s.lines().enumerate() {
-            ensure!(row < BINGO_SIZE, "card has too many rows");
-            for (col, v) in line.split_whitespace().enumerate() {
-                ensure!(col < BINGO_SIZE, "card has too many columns");
-                card.grid[row][col] = BingoValue::new(v.parse()?);
+        let mut width = None;
+        let mut height = 0;
+        let mut values = vec![];
+        for line in s.lines() {
+            let row = line
+                .split_whitespace()
+                .map(|v| v.parse())
+                .collect::<Result<Vec<Num>, _>>()?;
+
+            let expected = *width.get_or_insert(row.len());
+            ensure!(
+                row.len() == expected,
+                "card row {} has {} columns but expected {}",
+                height + 1,
+                row.len(),
+                expected
+            );
+
+            values.extend(row);
+            height += 1;
+        }
+
+        let width = width.unwrap_or_default();
+        ensure!(width > 0 && height > 0, "card is empty");
+
+        Ok(BingoCard::new(width, height, values))
+    }
+}
+
+// The progress of a single card during a game.
+struct CardState {
+    marked: Vec<bool>,
+    remaining: Vec<usize>,
+    unmarked: Num,
+    won: bool,
+}
+
+impl CardState {
+    fn new(card: &BingoCard) -> Self {
+        CardState {
+            marked: vec![false; card.values.len()],
+            remaining: card.win_lines.iter().map(|line| line.len()).collect(),
+            unmarked: card.values.iter().sum(),
+            won: false,
+        }
+    }
+
+    // Marks the cell and returns whether doing so completed one of the card's win lines.
+    fn mark(&mut self, card: &BingoCard, cell: usize) -> bool {
+        if self.marked[cell] {
+            return false;
+        }
+
+        self.marked[cell] = true;
+        self.unmarked -= card.values[cell];
+        for &line in &card.cell_lines[cell] {
+            self.remaining[line] -= 1;
+            if self.remaining[line] == 0 {
+                self.won = true;
             }
         }
 
-        Ok(card)
+        self.won
     }
 }
 
-#[derive(Clone, Copy)]
-struct BingoValue {
-    num: Num,
-    marked: bool,
+#[derive(Clone)]
+enum WinPattern {
+    Rows,
+    Columns,
+    Diagonals,
+    Corners,
+    Blackout,
+    Mask(CellMask),
 }
 
-impl BingoValue {
-    fn new(value: Num) -> Self {
-        BingoValue {
-            num: value,
-            marked: false,
+impl WinPattern {
+    // Returns the win lines of this pattern for a card of the given dimensions.
+    fn lines(&self, width: usize, height: usize) -> Result<Vec<Vec<usize>>> {
+        let lines = match self {
+            WinPattern::Rows => (0..height)
+                .map(|y| (0..width).map(|x| y * width + x).collect())
+                .collect(),
+            WinPattern::Columns => (0..width)
+                .map(|x| (0..height).map(|y| y * width + x).collect())
+                .collect(),
+            WinPattern::Diagonals => {
+                ensure!(width == height, "diagonals require a square card");
+                vec![
+                    (0..width).map(|i| i * width + i).collect(),
+                    (0..width).map(|i| i * width + width - 1 - i).collect(),
+                ]
+            }
+            WinPattern::Corners => {
+                let mut corners = vec![
+                    0,
+                    width - 1,
+                    (height - 1) * width,
+                    (height - 1) * width + width - 1,
+                ];
+                corners.sort_unstable();
+                corners.dedup();
+                vec![corners]
+            }
+            WinPattern::Blackout => vec![(0..width * height).collect()],
+            WinPattern::Mask(mask) => {
+                ensure!(
+                    mask.width == width && mask.height == height,
+                    "win mask is {}x{} but card is {}x{}",
+                    mask.width,
+                    mask.height,
+                    width,
+                    height
+                );
+                vec![mask.cells.clone()]
+            }
+        };
+
+        Ok(lines)
+    }
+}
+
+impl FromStr for WinPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rows" => Ok(WinPattern::Rows),
+            "columns" => Ok(WinPattern::Columns),
+            "diagonals" => Ok(WinPattern::Diagonals),
+            "corners" => Ok(WinPattern::Corners),
+            "blackout" => Ok(WinPattern::Blackout),
+            _ => match s.strip_prefix("mask:") {
+                Some(mask) => Ok(WinPattern::Mask(mask.parse()?)),
+                None => bail!("unknown win pattern: {}", s),
+            },
         }
     }
 }
 
-enum BingoResult {
-    Win(Num),
-    NoWin,
+// A custom win pattern written as rows separated by '/', where 'x' marks a cell that
+// must be marked and '.' a cell that is ignored, e.g. "x...x/.x.x./..x../.x.x./x...x".
+#[derive(Clone)]
+struct CellMask {
+    width: usize,
+    height: usize,
+    cells: Vec<usize>,
+}
+
+impl FromStr for CellMask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s.split('/').collect::<Vec<_>>();
+        let width = rows[0].len();
+        let height = rows.len();
+
+        let mut cells = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            ensure!(row.len() == width, "win mask rows must have the same width");
+            for (x, c) in row.chars().enumerate() {
+                match c {
+                    'x' => cells.push(y * width + x),
+                    '.' => {}
+                    _ => bail!("invalid win mask cell: {}", c),
+                }
+            }
+        }
+
+        ensure!(!cells.is_empty(), "win mask has no cells");
+
+        Ok(CellMask {
+            width,
+            height,
+            cells,
+        })
+    }
 }
@@ -1,8 +1,11 @@
 use anyhow::{bail, Context, Result};
 use std::{
     collections::HashMap,
-    io::{self, Read},
-    ops::{Add, AddAssign},
+    env, fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    ops::{Add, AddAssign, Range},
+    path::Path,
     str::FromStr,
 };
 
@@ -12,29 +15,132 @@ fn main() -> Result<()> {
 
     let lines = read_lines(&input)?;
 
-    part1(&lines);
-    part2(&lines);
+    // Optional arguments:
+    //   --dense                   count overlaps by materialising every point of every
+    //                             line rather than working from the line geometry
+    //   --at X,Y                  print the coverage at a point
+    //   --region X,Y X,Y MIN      print the number of points in a rectangle covered by
+    //                             more than MIN lines
+    //   --hottest                 print the most covered point
+    //   --heatmap PATH            write the coverage as a .pgm or .png image
+    //   --ascii                   print the coverage as ASCII art
+    //   --max-size N              scale exported maps down to at most N cells a side
+    let mut count: Counter = count_overlaps;
+    let mut queries = vec![];
+    let mut heatmap = None;
+    let mut ascii = false;
+    let mut max_size = None;
+    let mut args = env::args().skip(1);
+    let mut next = |name| args.next().with_context(|| format!("missing {}", name));
+    while let Ok(arg) = next("argument") {
+        match arg.as_str() {
+            "--dense" => count = Grid::count_overlaps,
+            "--at" => queries.push(Query::At(next("point")?.parse()?)),
+            "--region" => queries.push(Query::Region(
+                Rect::new(next("corner")?.parse()?, next("corner")?.parse()?),
+                next("threshold")?.parse()?,
+            )),
+            "--hottest" => queries.push(Query::Hottest),
+            "--heatmap" => {
+                let path = next("path")?;
+                let format = match Path::new(&path).extension().and_then(|ext| ext.to_str()) {
+                    Some("pgm") => ImageFormat::Pgm,
+                    Some("png") => ImageFormat::Png,
+                    _ => bail!("unsupported heatmap format: {}", path),
+                };
+                heatmap = Some((path, format));
+            }
+            "--ascii" => ascii = true,
+            "--max-size" => max_size = Some(next("size")?.parse()?),
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    part1(&lines, count);
+    part2(&lines, count);
+
+    // Parts 1 and 2 ignore lines at other slopes, so only count them when there are some.
+    if lines.iter().any(|line| line.kind == LineKind::Sloped) {
+        all_lines(&lines, count);
+    }
+
+    if queries.is_empty() && heatmap.is_none() && !ascii {
+        return Ok(());
+    }
+
+    let vents = VentMap::new(&lines);
+    for query in &queries {
+        match query {
+            Query::At(p) => println!("Coverage at {}: {}", p, vents.coverage(*p)),
+            Query::Region(rect, threshold) => println!(
+                "Points in {} covered more than {} times: {}",
+                rect,
+                threshold,
+                vents.count_above(rect, *threshold)
+            ),
+            Query::Hottest => match vents.hottest() {
+                Some((p, n)) => println!("Hottest point: {} covered {} times", p, n),
+                None => println!("Hottest point: no vents"),
+            },
+        }
+    }
+
+    if let Some((path, format)) = heatmap {
+        let map = vents
+            .heatmap(max_size.unwrap_or(Heatmap::IMAGE_SIZE))
+            .context("no vents to map")?;
+        let file = BufWriter::new(File::create(&path)?);
+        match format {
+            ImageFormat::Pgm => map.write_pgm(file)?,
+            ImageFormat::Png => map.write_png(file)?,
+        }
+    }
+
+    if ascii {
+        let map = vents
+            .heatmap(max_size.unwrap_or(Heatmap::ASCII_SIZE))
+            .context("no vents to map")?;
+        print!("{}", map.ascii());
+    }
 
     Ok(())
 }
 
-fn part1(lines: &[Line]) {
+enum Query {
+    At(Point),
+    Region(Rect, usize),
+    Hottest,
+}
+
+enum ImageFormat {
+    Pgm,
+    Png,
+}
+
+type Counter = fn(&[Line]) -> usize;
+
+fn part1(lines: &[Line], count: Counter) {
     let lines = lines
         .iter()
+        .filter(|line| matches!(line.kind, LineKind::Horizontal | LineKind::Vertical))
         .cloned()
-        .filter(|line| line.kind != LineKind::Diagonal)
         .collect::<Vec<_>>();
-    let grid = Grid::new(&lines);
-    let count = grid.vents.values().filter(|v| **v > 1).count();
 
-    println!("Part 1 answer: {}", count);
+    println!("Part 1 answer: {}", count(&lines));
 }
 
-fn part2(lines: &[Line]) {
-    let grid = Grid::new(lines);
-    let count = grid.vents.values().filter(|v| **v > 1).count();
+fn part2(lines: &[Line], count: Counter) {
+    let lines = lines
+        .iter()
+        .filter(|line| line.kind != LineKind::Sloped)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    println!("Part 2 answer: {}", count(&lines));
+}
 
-    println!("Part 2 answer: {}", count);
+fn all_lines(lines: &[Line], count: Counter) {
+    println!("All lines answer: {}", count(lines));
 }
 
 struct Grid {
@@ -53,6 +159,613 @@ impl Grid {
 
         Grid { vents }
     }
+
+    fn count_overlaps(lines: &[Line]) -> usize {
+        let grid = Grid::new(lines);
+        grid.vents.values().filter(|v| **v > 1).count()
+    }
+}
+
+// Answers coverage queries and draws heatmaps by sweeping over the rows of the field and
+// working out which lines cross each row, so that memory use depends on the number of
+// lines rather than the number of points they cover.
+struct VentMap {
+    // Sorted by the top row that they touch.
+    segments: Vec<Segment>,
+}
+
+impl VentMap {
+    fn new(lines: &[Line]) -> Self {
+        let mut segments = lines.iter().map(Segment::new).collect::<Vec<_>>();
+        segments.sort_unstable_by_key(|seg| seg.rows().0);
+
+        VentMap { segments }
+    }
+
+    fn coverage(&self, p: Point) -> usize {
+        let (x, y) = (p.x as i64, p.y as i64);
+        self.segments
+            .iter()
+            .filter(|seg| {
+                seg.row_span(y)
+                    .is_some_and(|(x1, x2)| (x1..=x2).contains(&x))
+            })
+            .count()
+    }
+
+    fn count_above(&self, rect: &Rect, threshold: usize) -> usize {
+        let (x1, x2) = (rect.min.x as i64, rect.max.x as i64);
+        let mut count = 0;
+        self.sweep(rect.min.y as i64, rect.max.y as i64, |_, runs| {
+            for (run, n) in runs {
+                if *n > threshold {
+                    count += (run.end.min(x2 + 1) - run.start.max(x1)).max(0) as usize;
+                }
+            }
+        });
+
+        count
+    }
+
+    // Returns the most covered point, preferring the smallest (y, x) on ties.
+    fn hottest(&self) -> Option<(Point, usize)> {
+        let bounds = self.bounds()?;
+        let mut hottest: Option<(Point, usize)> = None;
+        self.sweep(bounds.min.y as i64, bounds.max.y as i64, |y, runs| {
+            for (run, n) in runs {
+                if hottest.is_none_or(|(_, best)| *n > best) {
+                    hottest = Some((Point::new(run.start as i32, y as i32), *n));
+                }
+            }
+        });
+
+        hottest
+    }
+
+    fn bounds(&self) -> Option<Rect> {
+        let ends = self.segments.iter().flat_map(|seg| [seg.from, seg.to()]);
+        let (x1, x2, y1, y2) = ends.fold(None, |bounds, (x, y)| match bounds {
+            None => Some((x, x, y, y)),
+            Some((x1, x2, y1, y2)) => Some((x1.min(x), x2.max(x), y1.min(y), y2.max(y))),
+        })?;
+
+        Some(Rect::new(
+            Point::new(x1 as i32, y1 as i32),
+            Point::new(x2 as i32, y2 as i32),
+        ))
+    }
+
+    // Maps the coverage of the bounding box of the vents, shrinking it by a whole factor
+    // until neither side is longer than `max_size`. Each heatmap cell holds the highest
+    // coverage of the points it represents.
+    fn heatmap(&self, max_size: usize) -> Option<Heatmap> {
+        let bounds = self.bounds()?;
+        let (width, height) = bounds.size();
+        let scale = width.max(height).div_ceil(max_size.max(1)).max(1);
+        let (width, height) = (width.div_ceil(scale), height.div_ceil(scale));
+        let (min_x, min_y) = (bounds.min.x as i64, bounds.min.y as i64);
+
+        let mut cells = vec![0; width * height];
+        self.sweep(min_y, bounds.max.y as i64, |y, runs| {
+            let row = (y - min_y) as usize / scale * width;
+            for (run, n) in runs {
+                let first = (run.start - min_x) as usize / scale;
+                let last = (run.end - 1 - min_x) as usize / scale;
+                for cell in &mut cells[row + first..=row + last] {
+                    *cell = (*cell).max(*n);
+                }
+            }
+        });
+
+        Some(Heatmap {
+            width,
+            height,
+            cells,
+        })
+    }
+
+    // Calls `f` for each row from `y1` to `y2` that some line touches, with the half-open
+    // runs of x values in that row that share the same non-zero coverage, in order of x.
+    // Rows without lines are skipped.
+    fn sweep(&self, y1: i64, y2: i64, mut f: impl FnMut(i64, &[(Range<i64>, usize)])) {
+        let mut next = 0;
+        let mut active: Vec<&Segment> = vec![];
+        let mut events = vec![];
+        let mut runs = vec![];
+        let mut y = y1;
+        while y <= y2 {
+            while next < self.segments.len() && self.segments[next].rows().0 <= y {
+                active.push(&self.segments[next]);
+                next += 1;
+            }
+            active.retain(|seg| seg.rows().1 >= y);
+
+            if active.is_empty() {
+                match self.segments.get(next) {
+                    Some(seg) => y = seg.rows().0.max(y + 1),
+                    None => break,
+                }
+                continue;
+            }
+
+            events.clear();
+            for seg in &active {
+                if let Some((x1, x2)) = seg.row_span(y) {
+                    events.push((x1, 1));
+                    events.push((x2 + 1, -1));
+                }
+            }
+            events.sort_unstable();
+
+            runs.clear();
+            let mut depth = 0;
+            let mut i = 0;
+            while i < events.len() {
+                let x = events[i].0;
+                while i < events.len() && events[i].0 == x {
+                    depth += events[i].1;
+                    i += 1;
+                }
+                if let Some(&end) = events.get(i).map(|(x, _)| x) {
+                    if depth > 0 {
+                        runs.push((x..end, depth as usize));
+                    }
+                }
+            }
+
+            if !runs.is_empty() {
+                f(y, &runs);
+            }
+            y += 1;
+        }
+    }
+}
+
+struct Heatmap {
+    width: usize,
+    height: usize,
+    cells: Vec<usize>,
+}
+
+impl Heatmap {
+    const IMAGE_SIZE: usize = 1024;
+    const ASCII_SIZE: usize = 80;
+
+    // Grey levels from black for no vents to white for the most covered cell.
+    fn intensities(&self) -> Vec<u8> {
+        let max = self.cells.iter().copied().max().unwrap_or(0).max(1);
+        self.cells.iter().map(|n| (n * 255 / max) as u8).collect()
+    }
+
+    fn write_pgm<W: Write>(&self, mut w: W) -> Result<()> {
+        write!(w, "P5\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.intensities())?;
+        w.flush()?;
+
+        Ok(())
+    }
+
+    fn write_png<W: Write>(&self, w: W) -> Result<()> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.intensities())?;
+
+        Ok(())
+    }
+
+    // Draws the map in the puzzle's style: '.' for no vents, the coverage for 1 to 9 and
+    // '+' for anything higher.
+    fn ascii(&self) -> String {
+        let mut s = String::with_capacity((self.width + 1) * self.height);
+        for row in self.cells.chunks(self.width) {
+            for &n in row {
+                s.push(match n {
+                    0 => '.',
+                    1..=9 => char::from_digit(n as u32, 10).unwrap(),
+                    _ => '+',
+                });
+            }
+            s.push('\n');
+        }
+
+        s
+    }
+}
+
+// An axis-aligned rectangle including its edges.
+struct Rect {
+    min: Point,
+    max: Point,
+}
+
+impl Rect {
+    fn new(a: Point, b: Point) -> Self {
+        Rect {
+            min: Point::new(a.x.min(b.x), a.y.min(b.y)),
+            max: Point::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (
+            (self.max.x as i64 - self.min.x as i64 + 1) as usize,
+            (self.max.y as i64 - self.min.y as i64 + 1) as usize,
+        )
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.min, self.max)
+    }
+}
+
+// Counts the points covered by at least two lines without visiting the points of the
+// lines, in memory that grows with the number of lines rather than the number of shared
+// points. Points shared by collinear lines are counted from the overlapping runs of each
+// carrier (the infinite line that segments lie on), and every other shared point is a
+// crossing of lines on different carriers. Each crossing point is counted once, less one
+// for every carrier whose runs already counted it. Crossings of horizontal and vertical
+// lines are counted with a sweep over x, and every other crossing is counted from the
+// lines of the first sloped carrier through it.
+fn count_overlaps(lines: &[Line]) -> usize {
+    let segments = lines.iter().map(Segment::new).collect::<Vec<_>>();
+
+    let mut carriers: HashMap<Carrier, Vec<&Segment>> = HashMap::new();
+    for seg in &segments {
+        carriers.entry(seg.carrier).or_default().push(seg);
+    }
+
+    let mut count = 0;
+    let mut runs = HashMap::new();
+    for (carrier, segs) in &carriers {
+        let spans = segs.iter().map(|seg| seg.span()).collect();
+        let shared = shared_runs(spans, carrier.spacing());
+        count += shared
+            .iter()
+            .map(|r| (r.end - r.start) / carrier.spacing())
+            .sum::<i64>();
+        runs.insert(*carrier, shared);
+    }
+    let on_run = |carrier: &Carrier, p| carrier.covers(&runs[carrier], p);
+
+    let axis = segments
+        .iter()
+        .filter(|seg| seg.carrier.is_axis_aligned())
+        .collect::<Vec<_>>();
+    count += axis_crossings(&axis, &runs);
+
+    for (carrier, segs) in carriers.iter().filter(|(c, _)| !c.is_axis_aligned()) {
+        // The carriers of the other lines through each point where they cross this one.
+        // A line crosses the carrier at most once, so this holds no more than one point
+        // per line.
+        let mut crossings: HashMap<(i64, i64), Vec<Carrier>> = HashMap::new();
+        for a in segs {
+            for b in &segments {
+                if let Some(p) = a.crossing(b) {
+                    let through = crossings.entry(p).or_default();
+                    if !through.contains(&b.carrier) {
+                        through.push(b.carrier);
+                    }
+                }
+            }
+        }
+
+        for (p, through) in crossings {
+            let horizontal = through.iter().any(|c| c.dir == (1, 0));
+            let vertical = through.iter().any(|c| c.dir == (0, 1));
+            if horizontal && vertical {
+                // The sweep counted the point, but not the runs of this carrier.
+                count -= on_run(carrier, p) as i64;
+            } else if through.iter().all(|c| c.is_axis_aligned() || c > carrier) {
+                let counted = through.iter().filter(|c| on_run(c, p)).count();
+                count += 1 - on_run(carrier, p) as i64 - counted as i64;
+            }
+        }
+    }
+
+    count as usize
+}
+
+// Sweeps over x, keeping the rows of the horizontal carriers with a line, and with an
+// overlapping run, over the current x. At each vertical carrier it counts the points where
+// its lines cross a horizontal line, less those that lie on an overlapping run of either
+// carrier.
+fn axis_crossings(segments: &[&Segment], runs: &HashMap<Carrier, Vec<Range<i64>>>) -> i64 {
+    const START: u8 = 0;
+    const QUERY: u8 = 1;
+    const END: u8 = 2;
+    const LINE: usize = 0;
+    const RUN: usize = 1;
+
+    let mut rows = segments
+        .iter()
+        .filter(|seg| seg.carrier.dir == (1, 0))
+        .map(|seg| seg.from.1)
+        .collect::<Vec<_>>();
+    rows.sort_unstable();
+    rows.dedup();
+    let row = |y| rows.binary_search(&y).unwrap();
+    let between = |y1, y2| rows.partition_point(|&y| y < y1)..rows.partition_point(|&y| y <= y2);
+
+    let mut events = vec![];
+    for (i, seg) in segments.iter().enumerate() {
+        let (from, to) = (seg.from, seg.to());
+        if seg.carrier.dir == (1, 0) {
+            events.push((from.0.min(to.0), START, LINE, row(from.1)));
+            events.push((from.0.max(to.0), END, LINE, row(from.1)));
+        } else {
+            events.push((from.0, QUERY, LINE, i));
+        }
+    }
+    for &y in &rows {
+        for run in &runs[&Carrier::through((0, y), (1, 0))] {
+            events.push((run.start, START, RUN, row(y)));
+            events.push((run.end - 1, END, RUN, row(y)));
+        }
+    }
+    events.sort_unstable();
+
+    // The number of lines and runs over the current x in each row, and the rows with any.
+    let mut depth = vec![[0; 2]; rows.len()];
+    let mut active = [Fenwick::new(rows.len()), Fenwick::new(rows.len())];
+    let mut count = 0;
+    let mut i = 0;
+    while i < events.len() {
+        let (x, kind, which, id) = events[i];
+        if kind != QUERY {
+            let n = &mut depth[id][which];
+            match kind {
+                START => *n += 1,
+                _ => *n -= 1,
+            }
+            match (kind, *n) {
+                (START, 1) => active[which].add(id, 1),
+                (END, 0) => active[which].add(id, -1),
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+
+        // The rows covered by the vertical lines at this x, with overlapping lines merged.
+        let mut spans = vec![];
+        while i < events.len() && events[i].0 == x && events[i].1 == QUERY {
+            let seg = segments[events[i].3];
+            let (y1, y2) = (seg.from.1, seg.to().1);
+            spans.push((y1.min(y2), y1.max(y2)));
+            i += 1;
+        }
+        spans.sort_unstable();
+        let mut merged: Vec<(i64, i64)> = vec![];
+        for (y1, y2) in spans {
+            match merged.last_mut() {
+                Some(last) if y1 <= last.1 + 1 => last.1 = last.1.max(y2),
+                _ => merged.push((y1, y2)),
+            }
+        }
+
+        for (y1, y2) in merged {
+            count += active[LINE].sum(between(y1, y2)) - active[RUN].sum(between(y1, y2));
+        }
+        for run in &runs[&Carrier::through((x, 0), (0, 1))] {
+            count -= active[LINE].sum(between(run.start, run.end - 1));
+        }
+    }
+
+    count
+}
+
+// Counts at positions 0..n that can be updated and summed over ranges in O(log n).
+// See: https://en.wikipedia.org/wiki/Fenwick_tree
+struct Fenwick {
+    tree: Vec<i64>,
+}
+
+impl Fenwick {
+    fn new(n: usize) -> Self {
+        Fenwick {
+            tree: vec![0; n + 1],
+        }
+    }
+
+    fn add(&mut self, i: usize, delta: i64) {
+        let mut i = i + 1;
+        while i < self.tree.len() {
+            self.tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    fn prefix(&self, mut i: usize) -> i64 {
+        let mut sum = 0;
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+
+        sum
+    }
+
+    fn sum(&self, range: Range<usize>) -> i64 {
+        if range.start >= range.end {
+            return 0;
+        }
+
+        self.prefix(range.end) - self.prefix(range.start)
+    }
+}
+
+// Returns the half-open runs, in carrier position units, covered by at least two spans.
+fn shared_runs(spans: Vec<(i64, i64)>, spacing: i64) -> Vec<Range<i64>> {
+    let mut events = spans
+        .into_iter()
+        .flat_map(|(start, end)| [(start, 1), (end + spacing, -1)])
+        .collect::<Vec<_>>();
+    events.sort_unstable();
+
+    let mut runs = vec![];
+    let mut depth = 0;
+    let mut start = None;
+    let mut i = 0;
+    while i < events.len() {
+        let pos = events[i].0;
+        while i < events.len() && events[i].0 == pos {
+            depth += events[i].1;
+            i += 1;
+        }
+
+        if depth >= 2 {
+            start.get_or_insert(pos);
+        } else if let Some(start) = start.take() {
+            runs.push(start..pos);
+        }
+    }
+
+    runs
+}
+
+// The infinite line through a segment, identified by its primitive direction (pointing
+// right, or down when vertical) and its offset from the origin. Every lattice point on
+// it has a position along it, the dot product with the direction, and consecutive
+// lattice points are `spacing` apart.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Carrier {
+    dir: (i64, i64),
+    offset: i64,
+}
+
+impl Carrier {
+    fn through(p: (i64, i64), dir: (i64, i64)) -> Self {
+        Carrier {
+            dir,
+            offset: dir.0 * p.1 - dir.1 * p.0,
+        }
+    }
+
+    fn is_axis_aligned(&self) -> bool {
+        self.dir == (1, 0) || self.dir == (0, 1)
+    }
+
+    fn spacing(&self) -> i64 {
+        self.dir.0 * self.dir.0 + self.dir.1 * self.dir.1
+    }
+
+    fn position(&self, p: (i64, i64)) -> i64 {
+        self.dir.0 * p.0 + self.dir.1 * p.1
+    }
+
+    fn covers(&self, runs: &[Range<i64>], p: (i64, i64)) -> bool {
+        let pos = self.position(p);
+        let i = runs.partition_point(|r| r.end <= pos);
+        runs.get(i).is_some_and(|r| r.contains(&pos))
+    }
+}
+
+// A line as a start point, a primitive step and a number of steps.
+struct Segment {
+    from: (i64, i64),
+    step: (i64, i64),
+    steps: i64,
+    carrier: Carrier,
+}
+
+impl Segment {
+    fn new(line: &Line) -> Self {
+        let (step, steps) = line.step();
+        let (step, steps) = ((step.x as i64, step.y as i64), steps as i64);
+        let from = (line.from.x as i64, line.from.y as i64);
+
+        // Single point lines are treated as horizontal.
+        let dir = match step {
+            (0, 0) => (1, 0),
+            (x, y) if x < 0 || (x == 0 && y < 0) => (-x, -y),
+            _ => step,
+        };
+
+        Segment {
+            from,
+            step,
+            steps,
+            carrier: Carrier::through(from, dir),
+        }
+    }
+
+    fn to(&self) -> (i64, i64) {
+        (
+            self.from.0 + self.step.0 * self.steps,
+            self.from.1 + self.step.1 * self.steps,
+        )
+    }
+
+    // The top and bottom rows that the segment touches.
+    fn rows(&self) -> (i64, i64) {
+        let (a, b) = (self.from.1, self.to().1);
+        (a.min(b), a.max(b))
+    }
+
+    // Returns the range of x values that the segment covers in a row, if it touches it.
+    fn row_span(&self, y: i64) -> Option<(i64, i64)> {
+        let (top, bottom) = self.rows();
+        if !(top..=bottom).contains(&y) {
+            return None;
+        }
+
+        if self.step.1 == 0 {
+            let (a, b) = (self.from.0, self.to().0);
+            return Some((a.min(b), a.max(b)));
+        }
+
+        let dy = y - self.from.1;
+        if dy % self.step.1 != 0 {
+            return None;
+        }
+        let x = self.from.0 + dy / self.step.1 * self.step.0;
+        Some((x, x))
+    }
+
+    fn span(&self) -> (i64, i64) {
+        let (a, b) = (
+            self.carrier.position(self.from),
+            self.carrier.position(self.to()),
+        );
+        (a.min(b), a.max(b))
+    }
+
+    // Returns the lattice point shared with a non-parallel segment, if there is one.
+    fn crossing(&self, other: &Segment) -> Option<(i64, i64)> {
+        let cross = |a: (i64, i64), b: (i64, i64)| a.0 * b.1 - a.1 * b.0;
+
+        let (d1, d2) = (self.step_or_unit(), other.step_or_unit());
+        let denom = cross(d1, d2);
+        if denom == 0 {
+            return None;
+        }
+
+        let w = (other.from.0 - self.from.0, other.from.1 - self.from.1);
+        let (u, v) = (cross(w, d2), cross(w, d1));
+        if u % denom != 0 || v % denom != 0 {
+            return None;
+        }
+
+        let (u, v) = (u / denom, v / denom);
+        if !(0..=self.steps).contains(&u) || !(0..=other.steps).contains(&v) {
+            return None;
+        }
+
+        Some((self.from.0 + u * d1.0, self.from.1 + u * d1.1))
+    }
+
+    fn step_or_unit(&self) -> (i64, i64) {
+        if self.steps == 0 {
+            self.carrier.dir
+        } else {
+            self.step
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -67,14 +780,33 @@ enum LineKind {
     Horizontal,
     Vertical,
     Diagonal,
+    Sloped,
 }
 
 impl Line {
+    // Iterates over the integer lattice points of the line. Stepping by the delta
+    // divided by the gcd of its components visits exactly those points for any slope.
     fn iter(&self) -> LineIter {
+        let (step, steps) = self.step();
+
         LineIter {
-            line: self.clone(),
             curr: self.from,
-            done: false,
+            step,
+            remaining: steps + 1,
+        }
+    }
+}
+
+impl Line {
+    // Returns the primitive step between consecutive lattice points of the line and the
+    // number of steps from one end to the other.
+    fn step(&self) -> (Point, i32) {
+        let delta = Point::new(self.to.x - self.from.x, self.to.y - self.from.y);
+        let steps = gcd(delta.x.abs(), delta.y.abs());
+        if steps == 0 {
+            (Point::new(0, 0), 0)
+        } else {
+            (Point::new(delta.x / steps, delta.y / steps), steps)
         }
     }
 }
@@ -99,7 +831,7 @@ impl FromStr for Line {
         } else if (to.x - from.x).abs() == (to.y - from.y).abs() {
             LineKind::Diagonal
         } else {
-            bail!("invalid line type: {:?} -> {:?}", from, to)
+            LineKind::Sloped
         };
 
         Ok(Line { kind, from, to })
@@ -107,35 +839,37 @@ impl FromStr for Line {
 }
 
 struct LineIter {
-    line: Line,
     curr: Point,
-    done: bool,
+    step: Point,
+    remaining: i32,
 }
 
 impl Iterator for LineIter {
     type Item = Point;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.done {
+        if self.remaining == 0 {
             return None;
         }
 
         let curr = self.curr;
-
-        if self.curr == self.line.to {
-            self.done = true;
-            return Some(curr);
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.curr += self.step;
         }
 
-        let x_delta = (self.line.to.x - curr.x).max(-1).min(1);
-        let y_delta = (self.line.to.y - curr.y).max(-1).min(1);
-
-        self.curr += Point::new(x_delta, y_delta);
-
         Some(curr)
     }
 }
 
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Point {
     x: i32,
@@ -148,11 +882,17 @@ impl Point {
     }
 }
 
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
 impl FromStr for Point {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (x, y) = s.split_once(",").context("bad point")?;
+        let (x, y) = s.split_once(',').context("bad point")?;
 
         Ok(Point {
             x: x.parse()?,
@@ -177,3 +917,126 @@ impl AddAssign for Point {
         *self = *self + rhs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks the geometric count against the count from materialising every point.
+    fn check(input: &str) {
+        let lines = read_lines(input).unwrap();
+        assert_eq!(
+            count_overlaps(&lines),
+            Grid::count_overlaps(&lines),
+            "lines:\n{}",
+            input
+        );
+    }
+
+    #[test]
+    fn coincident_segments() {
+        check("0,0 -> 5,0\n0,0 -> 5,0");
+        check("0,0 -> 5,0\n5,0 -> 0,0\n2,0 -> 3,0");
+        check("0,0 -> 6,6\n2,2 -> 9,9\n4,4 -> 3,3");
+        check("0,0 -> 4,2\n2,1 -> 8,4\n6,3 -> 10,5");
+        check("1,1 -> 1,8\n1,4 -> 1,4\n1,8 -> 1,12");
+    }
+
+    #[test]
+    fn single_point_lines() {
+        check("3,3 -> 3,3");
+        check("3,3 -> 3,3\n3,3 -> 3,3");
+        check("3,3 -> 3,3\n0,3 -> 6,3");
+        check("2,4 -> 2,4\n0,0 -> 4,8");
+        check("1,0 -> 1,0\n0,0 -> 4,8");
+    }
+
+    #[test]
+    fn three_segments_crossing_at_one_point() {
+        check("0,4 -> 8,4\n4,0 -> 4,8\n0,0 -> 8,8");
+        check("0,4 -> 8,4\n4,0 -> 4,8\n0,8 -> 8,0\n0,0 -> 8,8");
+        check("0,2 -> 8,6\n0,6 -> 8,2\n4,0 -> 4,8");
+        check("0,0 -> 6,3\n0,3 -> 6,0\n3,0 -> 3,3\n2,1 -> 4,2");
+    }
+
+    // Random inputs mixing horizontal, vertical, diagonal and sloped lines.
+    fn random_inputs(cases: usize) -> Vec<String> {
+        // A fixed linear congruential generator keeps the cases reproducible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |n: i32| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((state >> 33) % n as u64) as i32
+        };
+
+        (0..cases)
+            .map(|_| {
+                let count = 1 + next(8);
+                (0..count)
+                    .map(|_| {
+                        let (x, y) = (next(12), next(12));
+                        let (dx, dy) = match next(4) {
+                            0 => (next(9) - 4, 0),
+                            1 => (0, next(9) - 4),
+                            2 => {
+                                let d = next(9) - 4;
+                                (d, if next(2) == 0 { d } else { -d })
+                            }
+                            _ => (next(9) - 4, next(9) - 4),
+                        };
+                        format!("{},{} -> {},{}", x, y, x + dx, y + dy)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_mixed_slopes() {
+        for input in random_inputs(500) {
+            check(&input);
+        }
+    }
+
+    #[test]
+    fn queries_match_dense_grid() {
+        for input in random_inputs(200) {
+            let lines = read_lines(&input).unwrap();
+            let (grid, vents) = (Grid::new(&lines), VentMap::new(&lines));
+
+            for y in -6..20 {
+                for x in -6..20 {
+                    let p = Point::new(x, y);
+                    let expected = grid.vents.get(&p).copied().unwrap_or(0);
+                    assert_eq!(vents.coverage(p), expected, "{} in:\n{}", p, input);
+                }
+            }
+
+            let rect = Rect::new(Point::new(2, 3), Point::new(9, 7));
+            for threshold in 0..3 {
+                let expected = grid
+                    .vents
+                    .iter()
+                    .filter(|(p, n)| {
+                        **n > threshold && (2..=9).contains(&p.x) && (3..=7).contains(&p.y)
+                    })
+                    .count();
+                assert_eq!(
+                    vents.count_above(&rect, threshold),
+                    expected,
+                    "in:\n{}",
+                    input
+                );
+            }
+
+            let expected = grid
+                .vents
+                .iter()
+                .max_by_key(|(p, n)| (**n, std::cmp::Reverse((p.y, p.x))))
+                .map(|(p, n)| (*p, *n));
+            assert_eq!(vents.hottest(), expected, "in:\n{}", input);
+        }
+    }
+}
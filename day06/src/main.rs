@@ -1,84 +1,571 @@
 use std::{
     collections::VecDeque,
-    io::{self, Read},
-    iter,
+    env, fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    ops::AddAssign,
     str::FromStr,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use num_bigint::BigUint;
 
 fn main() -> Result<()> {
     let mut input = String::new();
     io::stdin().read_to_string(&mut input)?;
 
-    let model: FishModel = input.parse()?;
+    let timers = parse_timers(&input)?;
 
-    part1(model.clone());
-    part2(model);
+    // Optional arguments:
+    //   --adult-reset N, --child-reset N   override the reproduction timers of the
+    //                                      default species; not allowed with --species
+    //   --species NAME:ADULT:CHILD[:AGE]   add a species with its own timers and an
+    //                                      optional mortality age; each input line
+    //                                      holds the timers of one species in order
+    //   --days N                           print the population after N days
+    //   --fast                             project with matrix exponentiation
+    //   --modulo P                         report populations modulo P (implies --fast)
+    //   --series N PATH                    write the daily populations up to day N as CSV
+    let mut resets = Resets::default();
+    let mut custom_resets = false;
+    let mut specs = vec![];
+    let mut extra_days = vec![];
+    let mut projection = Projection::Step;
+    let mut series = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--adult-reset" => {
+                resets.adult = value()?.parse()?;
+                custom_resets = true;
+            }
+            "--child-reset" => {
+                resets.child = value()?.parse()?;
+                custom_resets = true;
+            }
+            "--species" => specs.push(value()?.parse()?),
+            "--days" => extra_days.push(value()?.parse()?),
+            "--fast" => {
+                if let Projection::Step = projection {
+                    projection = Projection::Fast(None);
+                }
+            }
+            "--modulo" => projection = Projection::Fast(Some(value()?.parse()?)),
+            "--series" => series = Some((value()?.parse::<usize>()?, value()?)),
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    ensure!(
+        !custom_resets || specs.is_empty(),
+        "--adult-reset and --child-reset cannot be used with --species, \
+         give the timers as NAME:ADULT:CHILD instead"
+    );
+
+    if specs.is_empty() {
+        specs.push(SpeciesSpec {
+            name: "fish".to_string(),
+            resets,
+            mortality: None,
+        });
+    }
+
+    let model = FishModel::new(specs, &timers)?;
+
+    part1(&model, &projection)?;
+    part2(&model, &projection)?;
+
+    for days in extra_days {
+        let final_population = model.project(days, &projection)?;
+        println!("Population after {} days: {}", days, final_population);
+    }
+
+    if let Some((days, path)) = series {
+        let file = File::create(&path).with_context(|| format!("cannot create {}", path))?;
+        model.write_series_csv(days, BufWriter::new(file))?;
+    }
 
     Ok(())
 }
 
-fn part1(mut model: FishModel) {
-    let final_population = model.run(80);
+fn part1(model: &FishModel, projection: &Projection) -> Result<()> {
+    let final_population = model.project(80, projection)?;
     println!("Part 1 answer: {}", final_population);
+
+    Ok(())
 }
 
-fn part2(mut model: FishModel) {
-    let final_population = model.run(256);
+fn part2(model: &FishModel, projection: &Projection) -> Result<()> {
+    let final_population = model.project(256, projection)?;
     println!("Part 2 answer: {}", final_population);
+
+    Ok(())
 }
 
-const ADULT_RESET: usize = 6;
-const CHILD_RESET: usize = 8;
+enum Projection {
+    // Advance a copy of the model one day at a time.
+    Step,
+    // Use matrix exponentiation, optionally reporting populations modulo a prime.
+    Fast(Option<BigUint>),
+}
+
+#[derive(Clone, Copy)]
+struct Resets {
+    adult: usize,
+    child: usize,
+}
+
+impl Default for Resets {
+    fn default() -> Self {
+        Resets { adult: 6, child: 8 }
+    }
+}
 
+#[derive(Clone)]
+struct SpeciesSpec {
+    name: String,
+    resets: Resets,
+    mortality: Option<usize>,
+}
+
+impl FromStr for SpeciesSpec {
+    type Err = anyhow::Error;
+
+    // Parses a species of the form "<name>:<adult reset>:<child reset>[:<mortality age>]".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        ensure!(
+            parts.len() == 3 || parts.len() == 4,
+            "invalid species: {}",
+            s
+        );
+
+        Ok(SpeciesSpec {
+            name: parts[0].to_string(),
+            resets: Resets {
+                adult: parts[1].parse()?,
+                child: parts[2].parse()?,
+            },
+            mortality: parts.get(3).map(|age| age.parse()).transpose()?,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Species {
+    spec: SpeciesSpec,
+    // Without a mortality age, the fish are organized into bins where the bin index
+    // equals the number of days remaining before the fish in that bin can reproduce. A
+    // circular buffer lets us advance a day by rotating it.
+    //
+    // With a mortality age the bin index is instead the age of the fish, which also
+    // fixes its timer, and the oldest bin drops off the end each day. A fish's age is
+    // counted from when it would have been born given its initial timer.
+    bins: VecDeque<Count>,
+}
+
+impl Species {
+    fn new(spec: SpeciesSpec, timers: &[usize]) -> Result<Self> {
+        let resets = spec.resets;
+        ensure!(
+            resets.adult <= resets.child,
+            "{}: adult reset {} is longer than child reset {}",
+            spec.name,
+            resets.adult,
+            resets.child
+        );
+
+        let size = spec.mortality.unwrap_or(resets.child + 1);
+        let mut bins = VecDeque::from(vec![Count::default(); size]);
+        for &timer in timers {
+            ensure!(
+                timer <= resets.child,
+                "{}: timer {} is longer than child reset {}",
+                spec.name,
+                timer,
+                resets.child
+            );
+
+            let bin = match spec.mortality {
+                Some(_) => resets.child - timer,
+                None => timer,
+            };
+            ensure!(
+                bin < size,
+                "{}: timer {} means the fish is already past its mortality age",
+                spec.name,
+                timer
+            );
+            bins[bin] += &Count::Small(1);
+        }
+
+        Ok(Species { spec, bins })
+    }
+
+    // Returns whether fish of the given age reproduce today.
+    fn reproduces_at(&self, age: usize) -> bool {
+        let Resets { adult, child } = self.spec.resets;
+        age >= child && (age - child).is_multiple_of(adult + 1)
+    }
+
+    fn step(&mut self) {
+        match self.spec.mortality {
+            None => {
+                // Pop the zero bin and push it to the back - these are the new children.
+                // This is safe to unwrap since we control the number of bins. This has the
+                // effect of rotating the buffer forward and decrementing the fish's
+                // reproduction timer.
+                let zero_bin = self.bins.pop_front().unwrap();
+                self.bins.push_back(zero_bin.clone());
+
+                // Add the parents to the "reset bin".
+                self.bins[self.spec.resets.adult] += &zero_bin;
+            }
+            Some(_) => {
+                let mut births = Count::default();
+                for (age, bin) in self.bins.iter().enumerate() {
+                    if self.reproduces_at(age) {
+                        births += bin;
+                    }
+                }
+
+                // Everyone gets a day older and the oldest fish die.
+                self.bins.push_front(births);
+                self.bins.pop_back();
+            }
+        }
+    }
+
+    // Builds the matrix where entry (i, j) is the number of fish in bin i tomorrow for
+    // each fish in bin j today.
+    fn transition(&self) -> Matrix {
+        let size = self.bins.len();
+        let mut m = Matrix::zeroes(size);
+        match self.spec.mortality {
+            None => {
+                // Every timer counts down, fish at zero reset as adults and each one has a
+                // child.
+                let Resets { adult, child } = self.spec.resets;
+                for i in 0..child {
+                    m.cells[i * size + i + 1] += 1u32;
+                }
+                m.cells[adult * size] += 1u32;
+                m.cells[child * size] += 1u32;
+            }
+            Some(_) => {
+                for age in 0..size {
+                    if self.reproduces_at(age) {
+                        m.cells[age] += 1u32;
+                    }
+                    if age + 1 < size {
+                        m.cells[(age + 1) * size + age] += 1u32;
+                    }
+                }
+            }
+        }
+
+        m
+    }
+
+    fn total_population(&self) -> Count {
+        let mut total = Count::default();
+        for bin in &self.bins {
+            total += bin;
+        }
+
+        total
+    }
+}
+
+// Several species of fish that are advanced together. The species don't interact so the
+// total population is the sum of theirs.
 #[derive(Clone)]
 struct FishModel {
-    // We'll use a circular buffer to organize the fish into bins, where the bin index
-    // equals the number of days remaining before the fish in that bin can reproduce.
-    bins: VecDeque<usize>,
+    species: Vec<Species>,
 }
 
 impl FishModel {
-    fn run(&mut self, days: usize) -> usize {
-        for _ in 0..days {
-            // Pop the zero bin and add it to the "reset bin". This is safe to unwrap since
-            // we control the number of bins. This has the effect of rotating the buffer
-            // forward and decrementing the fish's reproduction timer.
-            let zero_bin = self.bins.pop_front().unwrap();
-            let reset_bin = &mut self.bins[ADULT_RESET];
-            *reset_bin += zero_bin;
+    // Creates a model where the nth species starts with the nth list of timers.
+    fn new(specs: Vec<SpeciesSpec>, timers: &[Vec<usize>]) -> Result<Self> {
+        ensure!(
+            timers.len() == specs.len(),
+            "input has timers for {} species but {} are defined",
+            timers.len(),
+            specs.len()
+        );
+
+        let species = specs
+            .into_iter()
+            .zip(timers)
+            .map(|(spec, timers)| Species::new(spec, timers))
+            .collect::<Result<_>>()?;
+
+        Ok(FishModel { species })
+    }
 
-            // Push the total fish in the zero bin to the back - these are the new children.
-            self.bins.push_back(zero_bin);
+    fn step(&mut self) {
+        for species in &mut self.species {
+            species.step();
+        }
+    }
+
+    fn run(&mut self, days: usize) -> Count {
+        for _ in 0..days {
+            self.step();
         }
 
         self.total_population()
     }
 
-    fn total_population(&self) -> usize {
-        self.bins.iter().sum()
+    fn project(&self, days: u64, projection: &Projection) -> Result<Count> {
+        match projection {
+            Projection::Step => Ok(self.clone().run(days.try_into()?)),
+            Projection::Fast(modulus) => self.run_fast(days, modulus.as_ref()),
+        }
+    }
+
+    // Projects the population without advancing the model by raising each species' daily
+    // bin transition matrix to the power of `days` with repeated squaring, which takes
+    // O(log days) matrix multiplications. With a modulus the population is reported
+    // modulo it, which keeps the arithmetic small for very large day counts.
+    fn run_fast(&self, days: u64, modulus: Option<&BigUint>) -> Result<Count> {
+        if let Some(m) = modulus {
+            ensure!(*m > BigUint::from(1u32), "modulus must be greater than 1");
+        }
+
+        let mut total = BigUint::default();
+        for species in &self.species {
+            let projection = species.transition().pow(days, modulus);
+            let bins = species.bins.iter().map(Count::to_big).collect::<Vec<_>>();
+            total += projection
+                .rows()
+                .flat_map(|row| row.iter().zip(&bins).map(|(a, b)| a * b))
+                .sum::<BigUint>();
+        }
+
+        if let Some(m) = modulus {
+            total %= m;
+        }
+
+        Ok(Count::from(total))
+    }
+
+    fn total_population(&self) -> Count {
+        let mut total = Count::default();
+        for species in &self.species {
+            total += &species.total_population();
+        }
+
+        total
+    }
+
+    // Returns the populations of every day, starting with the current one.
+    fn series(&self) -> Series {
+        Series {
+            model: self.clone(),
+            day: 0,
+        }
+    }
+
+    // Writes one row per day from day 0 to `days` with the population of every bin of
+    // every species and the total.
+    fn write_series_csv<W: Write>(&self, days: usize, mut w: W) -> Result<()> {
+        let mut header = vec!["day".to_string()];
+        for species in &self.species {
+            let kind = match species.spec.mortality {
+                Some(_) => "age",
+                None => "timer",
+            };
+            for i in 0..species.bins.len() {
+                header.push(format!("{}_{}{}", species.spec.name, kind, i));
+            }
+        }
+        header.push("total".to_string());
+        writeln!(w, "{}", header.join(","))?;
+
+        for snapshot in self.series().take(days + 1) {
+            let mut row = vec![snapshot.day.to_string()];
+            row.extend(snapshot.bins.iter().flatten().map(|n| n.to_string()));
+            row.push(snapshot.total.to_string());
+            writeln!(w, "{}", row.join(","))?;
+        }
+        w.flush()?;
+
+        Ok(())
     }
 }
 
-impl FromStr for FishModel {
-    type Err = anyhow::Error;
+struct Snapshot {
+    day: usize,
+    bins: Vec<Vec<Count>>,
+    total: Count,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let data = s
-            .trim()
-            .split(',')
-            .map(|v| v.parse().context("bad input"))
-            .collect::<Result<Vec<usize>>>()?;
+struct Series {
+    model: FishModel,
+    day: usize,
+}
 
-        let init = iter::repeat(0).take(CHILD_RESET + 1);
-        let mut bins = VecDeque::from_iter(init);
+impl Iterator for Series {
+    type Item = Snapshot;
 
-        for v in data {
-            let bin = &mut bins[v];
-            *bin += 1;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.day > 0 {
+            self.model.step();
         }
 
-        Ok(FishModel { bins })
+        let snapshot = Snapshot {
+            day: self.day,
+            bins: self
+                .model
+                .species
+                .iter()
+                .map(|species| species.bins.iter().cloned().collect())
+                .collect(),
+            total: self.model.total_population(),
+        };
+        self.day += 1;
+
+        Some(snapshot)
     }
 }
+
+// A population count that is kept in a u64 until it overflows and then switches to an
+// arbitrary-precision integer.
+#[derive(Clone)]
+enum Count {
+    Small(u64),
+    Big(BigUint),
+}
+
+impl Count {
+    fn to_big(&self) -> BigUint {
+        match self {
+            Count::Small(n) => BigUint::from(*n),
+            Count::Big(n) => n.clone(),
+        }
+    }
+}
+
+impl From<BigUint> for Count {
+    fn from(n: BigUint) -> Self {
+        match u64::try_from(&n) {
+            Ok(n) => Count::Small(n),
+            Err(_) => Count::Big(n),
+        }
+    }
+}
+
+impl Default for Count {
+    fn default() -> Self {
+        Count::Small(0)
+    }
+}
+
+impl AddAssign<&Count> for Count {
+    fn add_assign(&mut self, rhs: &Count) {
+        *self = match (&*self, rhs) {
+            (Count::Small(a), Count::Small(b)) => match a.checked_add(*b) {
+                Some(n) => Count::Small(n),
+                None => Count::Big(BigUint::from(*a) + b),
+            },
+            (a, b) => Count::Big(a.to_big() + b.to_big()),
+        };
+    }
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Count::Small(n) => write!(f, "{}", n),
+            Count::Big(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Matrix {
+    size: usize,
+    cells: Vec<BigUint>,
+}
+
+impl Matrix {
+    fn zeroes(size: usize) -> Self {
+        Matrix {
+            size,
+            cells: vec![BigUint::default(); size * size],
+        }
+    }
+
+    fn identity(size: usize) -> Self {
+        let mut m = Matrix::zeroes(size);
+        for i in 0..size {
+            m.cells[i * size + i] = BigUint::from(1u32);
+        }
+
+        m
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[BigUint]> {
+        self.cells.chunks(self.size)
+    }
+
+    fn mul(&self, rhs: &Matrix, modulus: Option<&BigUint>) -> Matrix {
+        let size = self.size;
+        let mut cells = vec![BigUint::default(); size * size];
+        for i in 0..size {
+            for k in 0..size {
+                let a = &self.cells[i * size + k];
+                if *a == BigUint::default() {
+                    continue;
+                }
+                for j in 0..size {
+                    cells[i * size + j] += a * &rhs.cells[k * size + j];
+                }
+            }
+        }
+
+        if let Some(m) = modulus {
+            for cell in &mut cells {
+                *cell %= m;
+            }
+        }
+
+        Matrix { size, cells }
+    }
+
+    fn pow(&self, mut exp: u64, modulus: Option<&BigUint>) -> Matrix {
+        let mut result = Matrix::identity(self.size);
+        let mut base = self.clone();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base, modulus);
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base, modulus);
+            }
+        }
+
+        result
+    }
+}
+
+// Parses one comma separated list of timers per species, one species per line.
+fn parse_timers(input: &str) -> Result<Vec<Vec<usize>>> {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.trim()
+                .split(',')
+                .map(|v| v.parse().context("bad input"))
+                .collect()
+        })
+        .collect()
+}
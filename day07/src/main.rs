@@ -1,65 +1,514 @@
-use std::io::{self, Read};
+use std::{
+    env,
+    io::{self, Read},
+    str::FromStr,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 
 fn main() -> Result<()> {
     let mut input = String::new();
     io::stdin().read_to_string(&mut input)?;
 
-    let values = read_values(&input)?;
+    // Optional arguments:
+    //   --search            find the 1D alignments with the generic convex search rather
+    //                       than the closed-form shortcuts
+    //   --cost MODEL        also align using a custom fuel cost model (see `parse_cost`)
+    //   --points            read each line as the comma separated coordinates of one crab
+    //                       rather than a single line of horizontal positions
+    //   --metric METRIC     measure distances between points with "manhattan" (the
+    //                       default) or "euclidean"
+    let mut search = false;
+    let mut coordinates = false;
+    let mut custom = None;
+    let mut metric = Metric::Manhattan;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--search" => search = true,
+            "--cost" => custom = Some(parse_cost(&args.next().context("missing cost")?)?),
+            "--points" => coordinates = true,
+            "--metric" => metric = args.next().context("missing metric")?.parse()?,
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    if !coordinates {
+        let values = read_values(&input)?;
+
+        part1(&values, search);
+        part2(&values, search);
+
+        if let Some(cost) = custom {
+            ensure_crabs(cost.as_ref(), values.len())?;
+            let best = align_convex(&values, cost.as_ref());
+            println!(
+                "Custom cost answer: {} (align at {})",
+                best.cost, best.position
+            );
+        }
+    } else {
+        let points = read_points(&input)?;
+
+        let mut models: Vec<(&str, Box<dyn FuelCost>)> = vec![
+            ("Part 1", Box::new(Linear)),
+            ("Part 2", Box::new(Triangular)),
+        ];
+        if let Some(cost) = custom {
+            models.push(("Custom cost", cost));
+        }
+
+        for (name, cost) in models {
+            ensure_crabs(cost.as_ref(), points.len())?;
+            let best = align_points(&points, metric, cost.as_ref());
+            let position = best
+                .position
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>();
+            println!(
+                "{} answer: {} (align at {})",
+                name,
+                best.cost,
+                position.join(",")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn part1(values: &[u32], search: bool) {
+    let best = if search {
+        align_convex(values, &Linear)
+    } else {
+        align_median(values)
+    };
+
+    println!("Part 1 answer: {} (align at {})", best.cost, best.position);
+}
+
+fn part2(values: &[u32], search: bool) {
+    let best = if search {
+        align_convex(values, &Triangular)
+    } else {
+        align_mean(values)
+    };
+
+    println!("Part 2 answer: {} (align at {})", best.cost, best.position);
+}
+
+// The fuel a crab uses to move a distance. Costs must not decrease with distance and must
+// be convex in it, which lets alignments be found by search rather than brute force.
+trait FuelCost {
+    fn cost(&self, crab: usize, distance: u64) -> u64;
+
+    // The number of crabs the model has settings for, if it is limited.
+    fn crabs(&self) -> Option<usize> {
+        None
+    }
+}
+
+// One unit of fuel per unit moved.
+struct Linear;
+
+impl FuelCost for Linear {
+    fn cost(&self, _: usize, distance: u64) -> u64 {
+        distance
+    }
+}
+
+// Each unit moved costs one more than the last.
+// See: https://en.wikipedia.org/wiki/Triangular_number
+struct Triangular;
+
+impl FuelCost for Triangular {
+    fn cost(&self, _: usize, distance: u64) -> u64 {
+        distance * (distance + 1) / 2
+    }
+}
+
+struct Quadratic;
 
-    part1(&values);
-    part2(&values);
+impl FuelCost for Quadratic {
+    fn cost(&self, _: usize, distance: u64) -> u64 {
+        distance * distance
+    }
+}
+
+// Scales another cost model by a weight per crab.
+struct Weighted {
+    weights: Vec<u64>,
+    base: Box<dyn FuelCost>,
+}
+
+impl FuelCost for Weighted {
+    fn cost(&self, crab: usize, distance: u64) -> u64 {
+        self.weights[crab] * self.base.cost(crab, distance)
+    }
+
+    fn crabs(&self) -> Option<usize> {
+        Some(self.weights.len())
+    }
+}
+
+// Parses "linear", "triangular", "quadratic" or "weighted:<model>:<w1>,<w2>,..." where
+// the weights apply to the crabs in input order.
+fn parse_cost(s: &str) -> Result<Box<dyn FuelCost>> {
+    match s {
+        "linear" => Ok(Box::new(Linear)),
+        "triangular" => Ok(Box::new(Triangular)),
+        "quadratic" => Ok(Box::new(Quadratic)),
+        _ => {
+            let spec = s
+                .strip_prefix("weighted:")
+                .with_context(|| format!("unknown cost model: {}", s))?;
+            let (base, weights) = spec.rsplit_once(':').context("missing weights")?;
+            let weights = weights
+                .split(',')
+                .map(|w| w.parse().context("bad weight"))
+                .collect::<Result<_>>()?;
+
+            Ok(Box::new(Weighted {
+                weights,
+                base: parse_cost(base)?,
+            }))
+        }
+    }
+}
+
+fn ensure_crabs(cost: &dyn FuelCost, crabs: usize) -> Result<()> {
+    if let Some(n) = cost.crabs() {
+        ensure!(
+            n == crabs,
+            "cost model has settings for {} crabs but there are {}",
+            n,
+            crabs
+        );
+    }
 
     Ok(())
 }
 
-fn part1(values: &[u32]) {
-    let min_cost = min_cost(values, simple_cost);
-    println!("Part 1 answer: {}", min_cost);
+struct Alignment {
+    position: u32,
+    cost: u64,
 }
 
-fn part2(values: &[u32]) {
-    let min_cost = min_cost(values, triangular_cost);
-    println!("Part 2 answer: {}", min_cost);
+// The sum of distances is minimised at the median.
+fn align_median(values: &[u32]) -> Alignment {
+    let mut sorted = values.to_vec();
+    let mid = (sorted.len() - 1) / 2;
+    let position = *sorted.select_nth_unstable(mid).1;
+
+    Alignment {
+        position,
+        cost: total_cost(position, values, &Linear),
+    }
 }
 
-fn min_cost<F>(values: &[u32], f: F) -> u32
-where
-    F: Fn(u32, &[u32]) -> u32,
-{
+// The sum of triangular distances is minimised within half a unit of the mean, so only
+// the integers around the mean need to be tried.
+fn align_mean(values: &[u32]) -> Alignment {
+    let sum = values.iter().map(|&v| v as u64).sum::<u64>();
+    let mean = sum / values.len() as u64;
+
     let min = *values.iter().min().unwrap();
     let max = *values.iter().max().unwrap();
 
-    let mut min_cost = u32::MAX;
-    for v in min..=max {
-        let cost = f(v, values);
-        if cost < min_cost {
-            min_cost = cost;
+    (mean.saturating_sub(1)..=mean + 2)
+        .map(|p| p.clamp(min as u64, max as u64) as u32)
+        .map(|position| Alignment {
+            position,
+            cost: total_cost(position, values, &Triangular),
+        })
+        .min_by_key(|a| (a.cost, a.position))
+        .unwrap()
+}
+
+// Finds the cheapest position for any convex cost model by binary searching for the
+// first position where the total cost stops decreasing.
+fn align_convex(values: &[u32], cost: &dyn FuelCost) -> Alignment {
+    let mut lo = *values.iter().min().unwrap();
+    let mut hi = *values.iter().max().unwrap();
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if total_cost(mid, values, cost) <= total_cost(mid + 1, values, cost) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
         }
     }
 
-    min_cost
+    Alignment {
+        position: lo,
+        cost: total_cost(lo, values, cost),
+    }
 }
 
-fn simple_cost(value: u32, values: &[u32]) -> u32 {
+fn total_cost(value: u32, values: &[u32], cost: &dyn FuelCost) -> u64 {
     values
         .iter()
-        .fold(0, |acc, x| acc + (*x as i32 - value as i32).abs()) as u32
+        .enumerate()
+        .map(|(crab, x)| cost.cost(crab, x.abs_diff(value) as u64))
+        .sum()
 }
 
-// See: https://en.wikipedia.org/wiki/Triangular_number
-fn triangular_cost(value: u32, values: &[u32]) -> u32 {
-    values.iter().fold(0, |acc, x| {
-        let n = (*x as i32 - value as i32).abs();
-        acc + (n * (n + 1) / 2)
-    }) as u32
+#[derive(Clone, Copy)]
+enum Metric {
+    Manhattan,
+    // Distances are rounded up to whole units of movement.
+    Euclidean,
+}
+
+impl Metric {
+    fn distance(&self, a: &[i64], b: &[i64]) -> u64 {
+        let deltas = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b));
+        match self {
+            Metric::Manhattan => deltas.sum(),
+            Metric::Euclidean => {
+                let squared = deltas.map(|d| d * d).sum::<u64>();
+                let root = squared.isqrt();
+                if root * root == squared {
+                    root
+                } else {
+                    root + 1
+                }
+            }
+        }
+    }
+
+    fn exact_distance(&self, a: &[i64], b: &[i64]) -> f64 {
+        let deltas = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as f64);
+        match self {
+            Metric::Manhattan => deltas.sum(),
+            Metric::Euclidean => deltas.map(|d| d * d).sum::<f64>().sqrt(),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manhattan" => Ok(Metric::Manhattan),
+            "euclidean" => Ok(Metric::Euclidean),
+            _ => bail!("unknown metric: {}", s),
+        }
+    }
+}
+
+struct PointAlignment {
+    position: Vec<i64>,
+    cost: u64,
+}
+
+// Aligns crabs in any number of dimensions. First it descends from the per-axis median:
+// at each step size, starting from the largest power of two within the spread of the
+// crabs, it moves to the cheapest neighbouring point (including diagonal neighbours)
+// until none is cheaper, then halves the step. The descent can stop at a local minimum,
+// for instance when rounding Euclidean distances up to whole units, so it is followed by
+// a search of every position that could still be cheaper (see `visit_candidates`).
+fn align_points(points: &[Vec<i64>], metric: Metric, cost: &dyn FuelCost) -> PointAlignment {
+    let dims = points[0].len();
+    let total = |p: &[i64]| -> u64 {
+        points
+            .iter()
+            .enumerate()
+            .map(|(crab, x)| cost.cost(crab, metric.distance(x, p)))
+            .sum()
+    };
+
+    let mut position = (0..dims)
+        .map(|axis| {
+            let mut coords = points.iter().map(|p| p[axis]).collect::<Vec<_>>();
+            let mid = (coords.len() - 1) / 2;
+            *coords.select_nth_unstable(mid).1
+        })
+        .collect::<Vec<_>>();
+    let mut best = total(&position);
+
+    let spread = (0..dims)
+        .map(|axis| {
+            let min = points.iter().map(|p| p[axis]).min().unwrap();
+            let max = points.iter().map(|p| p[axis]).max().unwrap();
+            max.abs_diff(min)
+        })
+        .max()
+        .unwrap_or(0);
+
+    // Every direction in {-1, 0, 1}^dims except staying put.
+    let directions = (0..3usize.pow(dims as u32))
+        .map(|mut n| {
+            (0..dims)
+                .map(|_| {
+                    let d = (n % 3) as i64 - 1;
+                    n /= 3;
+                    d
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|dir| dir.iter().any(|&d| d != 0))
+        .collect::<Vec<_>>();
+
+    let mut step = spread.max(1).ilog2();
+    loop {
+        let size = 1 << step;
+        loop {
+            let (cheapest, next) = directions
+                .iter()
+                .map(|dir| {
+                    let p = position
+                        .iter()
+                        .zip(dir)
+                        .map(|(x, d)| x + d * size)
+                        .collect::<Vec<_>>();
+                    (total(&p), p)
+                })
+                .min_by_key(|(c, _)| *c)
+                .unwrap();
+
+            if cheapest >= best {
+                break;
+            }
+            best = cheapest;
+            position = next;
+        }
+
+        if step == 0 {
+            break;
+        }
+        step -= 1;
+    }
+
+    // Spreading each crab's cost linearly between whole distances, and measuring exact
+    // distances, bounds the total cost from below with a convex function.
+    let lower_bound = |p: &[i64]| -> f64 {
+        points
+            .iter()
+            .enumerate()
+            .map(|(crab, x)| {
+                let distance = metric.exact_distance(&x[..p.len()], p);
+                let whole = distance.floor();
+                let below = cost.cost(crab, whole as u64) as f64;
+                let above = cost.cost(crab, whole as u64 + 1) as f64;
+                below + (above - below) * (distance - whole)
+            })
+            .sum()
+    };
+
+    // Moving a position into the bounding box of the crabs brings it no further from any
+    // of them, so the box holds a best alignment.
+    let (lo, hi): (Vec<_>, Vec<_>) = (0..dims)
+        .map(|axis| {
+            let coords = points.iter().map(|p| p[axis]);
+            (coords.clone().min().unwrap(), coords.max().unwrap())
+        })
+        .unzip();
+
+    // Costs are whole numbers, so a cheaper position costs at least one less, and a bound
+    // half a unit below the best leaves room for rounding errors.
+    let limit = best as f64 - 0.5;
+    let mut candidate = vec![];
+    visit_candidates(&mut candidate, &lo, &hi, &lower_bound, limit, &mut |p| {
+        let cost = total(p);
+        if cost < best {
+            best = cost;
+            position = p.to_vec();
+        }
+    });
+
+    PointAlignment {
+        position,
+        cost: best,
+    }
+}
+
+// Calls `f` with every position between `lo` and `hi` where the convex `bound` is below
+// `limit`, fixing one axis at a time. With some axes fixed the bound is taken over the
+// fixed axes only, which is still a lower bound for every position that extends them, and
+// it is convex along the next axis so the positions within the limit on that axis form a
+// single run around its minimum.
+fn visit_candidates(
+    prefix: &mut Vec<i64>,
+    lo: &[i64],
+    hi: &[i64],
+    bound: &dyn Fn(&[i64]) -> f64,
+    limit: f64,
+    f: &mut dyn FnMut(&[i64]),
+) {
+    let axis = prefix.len();
+    if axis == lo.len() {
+        f(prefix);
+        return;
+    }
+
+    let mut at = |x| {
+        prefix.push(x);
+        let value = bound(prefix);
+        prefix.pop();
+        value
+    };
+
+    let (mut start, mut end) = (lo[axis], hi[axis]);
+    while start < end {
+        let mid = start + (end - start) / 2;
+        if at(mid) <= at(mid + 1) {
+            end = mid;
+        } else {
+            start = mid + 1;
+        }
+    }
+
+    if at(start) >= limit {
+        return;
+    }
+    while start > lo[axis] && at(start - 1) < limit {
+        start -= 1;
+    }
+    while end < hi[axis] && at(end + 1) < limit {
+        end += 1;
+    }
+
+    for x in start..=end {
+        prefix.push(x);
+        visit_candidates(prefix, lo, hi, bound, limit, f);
+        prefix.pop();
+    }
 }
 
 fn read_values(input: &str) -> Result<Vec<u32>> {
+    ensure!(
+        input.trim().lines().count() <= 1,
+        "expected a single line of positions, use --points for one crab per line"
+    );
+
     input
         .trim()
         .split(',')
         .map(|v| v.parse().context("bad input"))
         .collect()
 }
+
+fn read_points(input: &str) -> Result<Vec<Vec<i64>>> {
+    let points = input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.trim()
+                .split(',')
+                .map(|v| v.trim().parse().context("bad input"))
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let dims = points.first().context("bad input")?.len();
+    ensure!(
+        points.iter().all(|p| p.len() == dims),
+        "crabs must all have the same number of coordinates"
+    );
+
+    Ok(points)
+}
@@ -1,287 +1,991 @@
-use anyhow::{bail, Context, Result};
-use lazy_static::lazy_static;
-use std::{collections::{HashMap, HashSet}, fmt::Display, io::{self, Read}, str::FromStr};
-use std::hash::{Hash, Hasher};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, Read},
+    str::FromStr,
+};
 
 fn main() -> Result<()> {
+    // Optional arguments:
+    //   --display PATH      load the display alphabet and glyphs from a definition file (see
+    //                       `DisplayDefinition::from_str`) rather than using 7-segment digits
+    //   --decode            print the decoded outputs of every entry
+    //   --repair            repair entries where a single segment of one sample or output was
+    //                       flipped, reporting each repair
+    //   --encode WIRES LABELS
+    //                       print the entry a display wired with WIRES (the wire connected to
+    //                       each segment, in order) shows for the glyphs named by LABELS (one
+    //                       per character, or separated by commas) instead of decoding stdin
+    //   --verify            check that every possible wiring of the display can be decoded
+    //                       instead of decoding stdin
+    let mut display = SEVEN_SEGMENT_DIGITS.parse::<DisplayDefinition>()?;
+    let mut show_decoded = false;
+    let mut repair = false;
+    let mut encode = None;
+    let mut verify = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--display" => {
+                let path = args.next().context("missing display definition")?;
+                display = fs::read_to_string(&path)
+                    .with_context(|| format!("could not read {}", path))?
+                    .parse()?;
+                display.check()?;
+            }
+            "--decode" => show_decoded = true,
+            "--repair" => repair = true,
+            "--encode" => {
+                let wires = args.next().context("missing wires")?;
+                let labels = args.next().context("missing labels")?;
+                encode = Some((wires, labels));
+            }
+            "--verify" => verify = true,
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    if let Some((wires, labels)) = encode {
+        let labels = if labels.contains(',') {
+            labels.split(',').map(str::to_string).collect::<Vec<_>>()
+        } else {
+            labels.chars().map(String::from).collect()
+        };
+        let labels = labels.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let encoder = Encoder::new(&display, &wires)?;
+        println!("{}", encoder.encode(&labels)?.format(&display));
+
+        return Ok(());
+    }
+
+    if verify {
+        let count = verify_wirings(&display)?;
+        println!("Verified {} wirings", count);
+
+        return Ok(());
+    }
+
     let mut input = String::new();
     io::stdin().read_to_string(&mut input)?;
 
-    let entries = read_entries(&input)?;
+    let entries = read_entries(&input, &display)?;
+    let outputs = decode_entries(&entries, &display, repair)?;
 
-    part1(&entries)?;
-    part2(&entries)?;
+    part1(&outputs, &display);
+    if display.is_numeric() {
+        part2(&outputs)?;
+    }
+
+    if show_decoded {
+        for output in &outputs {
+            println!("{}", output.concat());
+        }
+    }
 
     Ok(())
 }
 
-fn part1(entries: &[Entry]) -> Result<()> {
-    let looking_for = vec![1, 4, 7, 8].into_iter().collect::<HashSet<u32>>();
+// Counts the outputs showing a glyph that no other glyph shares a segment count with (for
+// 7-segment digits, the digits 1, 4, 7 and 8).
+fn part1(outputs: &[Vec<&str>], display: &DisplayDefinition) {
+    let count = outputs
+        .iter()
+        .flatten()
+        .filter(|label| display.has_unique_length(label))
+        .count();
 
-    let mut count = 0;
-    for entry in entries {
-        let decoder = Decoder::build(&entry.samples)?;
-        for pattern in &entry.outputs {
-            let digit = decoder.decode(pattern)?;
-            if looking_for.contains(&digit) {
-                count += 1;
-            }
-        }
+    println!("Part 1 answer: {}", count);
+}
+
+fn part2(outputs: &[Vec<&str>]) -> Result<()> {
+    let mut sum = 0;
+    for output in outputs {
+        sum += output.concat().parse::<u64>()?;
     }
 
-    println!("Part 1 answer: {}", count);
+    println!("Part 2 answer: {}", sum);
 
     Ok(())
 }
 
-fn part2(entries: &[Entry]) -> Result<()> {
-    let mut sum = 0;
-    for entry in entries {
-        let decoder = Decoder::build(&entry.samples)?;
-        let mut output = 0;
+// Decodes the outputs of every entry to the labels of the glyphs they show.
+fn decode_entries<'a>(
+    entries: &[Entry],
+    display: &'a DisplayDefinition,
+    repair: bool,
+) -> Result<Vec<Vec<&'a str>>> {
+    let mut decoded = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        let context = || format!("could not decode entry {}", i + 1);
+
+        let decoder = if repair {
+            let (decoder, fix) =
+                Decoder::build_repaired(display, &entry.samples).with_context(context)?;
+            if let Some(fix) = fix {
+                println!("Entry {}: {}", i + 1, fix);
+            }
+            decoder
+        } else {
+            Decoder::build(display, &entry.samples).with_context(context)?
+        };
+
+        let mut output = vec![];
         for pattern in &entry.outputs {
-            let digit = decoder.decode(pattern)?;
-            output = output * 10 + digit;
+            let label = if repair {
+                let (label, fix) = decoder.decode_nearest(pattern).with_context(context)?;
+                if let Some(fix) = fix {
+                    println!("Entry {}: {}", i + 1, fix);
+                }
+                label
+            } else {
+                decoder.decode(pattern).with_context(context)?
+            };
+            output.push(label);
         }
 
-        sum += output;
+        decoded.push(output);
     }
 
-    println!("Part 2 answer: {}", sum);
+    Ok(decoded)
+}
 
-    Ok(())
+// Encodes every glyph through every possible wiring of the display and checks that the
+// decoder recovers both the wiring and the glyphs. Returns the number of wirings checked.
+fn verify_wirings(display: &DisplayDefinition) -> Result<usize> {
+    let labels = display
+        .glyphs
+        .iter()
+        .map(|g| g.label.as_str())
+        .collect::<Vec<_>>();
+
+    let mut wires = (0..display.segments.len()).collect::<Vec<_>>();
+    let mut count = 0;
+    loop {
+        let encoder = Encoder {
+            display,
+            wires: wires.clone(),
+        };
+        let line = encoder.encode(&labels)?.format(display);
+        let entry = Entry::parse(&line, display)?;
+
+        let decoder = Decoder::build(display, &entry.samples)
+            .with_context(|| format!("could not decode wiring {}", encoder))?;
+        let decoded = entry
+            .outputs
+            .iter()
+            .map(|pattern| decoder.decode(pattern))
+            .collect::<Result<Vec<_>>>()?;
+
+        let recovered = wires
+            .iter()
+            .enumerate()
+            .all(|(segment, &wire)| decoder.mapping[wire] == segment);
+        ensure!(
+            recovered && decoded == labels,
+            "decoded wiring {} incorrectly",
+            encoder
+        );
+
+        count += 1;
+        if !next_permutation(&mut wires) {
+            break;
+        }
+    }
+
+    Ok(count)
 }
 
-fn read_entries(input: &str) -> Result<Vec<Entry>> {
-    input.lines().map(|line| line.parse()).collect()
+// Rearranges the values into the next permutation in lexicographic order, returning false
+// once they are in descending order.
+fn next_permutation(values: &mut [usize]) -> bool {
+    let pivot = match (1..values.len()).rev().find(|&i| values[i - 1] < values[i]) {
+        Some(i) => i - 1,
+        None => return false,
+    };
+
+    let successor = (pivot + 1..values.len())
+        .rev()
+        .find(|&i| values[i] > values[pivot])
+        .unwrap();
+    values.swap(pivot, successor);
+    values[pivot + 1..].reverse();
+
+    true
+}
+
+fn read_entries(input: &str, display: &DisplayDefinition) -> Result<Vec<Entry>> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            Entry::parse(line, display).with_context(|| format!("line {}: bad entry", i + 1))
+        })
+        .collect()
 }
 
 type Segment = char;
-type Digit = u32;
-type SegmentMapping = HashMap<Segment, Segment>;
-type DivergentSegmentMapping = HashMap<Segment, SegmentSet>;
 
-#[derive(Clone, Eq)]
-pub struct SegmentSet(HashSet<Segment>);
+// The most segments a display can have.
+const MAX_SEGMENTS: usize = u32::BITS as usize;
+
+// The most glyphs a display can have.
+const MAX_GLYPHS: usize = u128::BITS as usize;
+
+// The standard 7-segment display showing the digits 0 to 9.
+const SEVEN_SEGMENT_DIGITS: &str = "\
+segments: abcdefg
+0: abcefg
+1: cf
+2: acdeg
+3: acdfg
+4: bcdf
+5: abdfg
+6: abdefg
+7: acf
+8: abcdefg
+9: abcdfg
+";
+
+// A set of display segments stored as a bitmask where bit i represents the i-th segment of
+// the display alphabet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SegmentSet(u32);
 
 impl SegmentSet {
+    fn single(index: usize) -> Self {
+        SegmentSet(1 << index)
+    }
+
+    // The set of the first n segments.
+    fn all(n: usize) -> Self {
+        SegmentSet(u32::MAX.checked_shl(n as u32).map_or(u32::MAX, |m| !m))
+    }
+
     fn len(&self) -> usize {
-        self.0.len()
+        self.0.count_ones() as usize
     }
 
     fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.0 == 0
     }
 
-    fn as_hashset(&self) -> &HashSet<Segment> {
-        &self.0
+    fn contains(&self, index: usize) -> bool {
+        self.0 & (1 << index) != 0
     }
-}
 
-impl FromStr for SegmentSet {
-    type Err = anyhow::Error;
+    fn union(&self, other: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 | other.0)
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let segments = s.chars().collect();
-        Ok(SegmentSet(segments))
+    fn intersection(&self, other: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 & other.0)
     }
-}
 
-impl Display for SegmentSet {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut v = self.0.iter().collect::<Vec<_>>();
-        v.sort_unstable();
+    fn difference(&self, other: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 & !other.0)
+    }
 
-        f.write_str(&String::from_iter(v.into_iter()))
+    fn symmetric_difference(&self, other: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 ^ other.0)
     }
-}
 
-impl PartialEq for SegmentSet {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+    // The index of the lowest segment in the set.
+    fn first(&self) -> Option<usize> {
+        (!self.is_empty()).then(|| self.0.trailing_zeros() as usize)
     }
-}
 
-impl Hash for SegmentSet {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.to_string().hash(state);
+    fn indices(&self) -> impl Iterator<Item = usize> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || {
+            let index = (bits != 0).then(|| bits.trailing_zeros() as usize)?;
+            bits &= bits - 1;
+            Some(index)
+        })
     }
 }
 
-lazy_static! {
-    // All possible segments.
-    static ref ALL_SEGMENTS: HashSet<Segment> = "abcdefg"
-        .chars().collect();
-
-    // Internal (non-pub) vector of pattern-digit pairs ordered by pattern
-    // length. This structure is used for building the following structures.
-    static ref SEGMENT_SET_DIGIT_PAIRS: Vec<(SegmentSet, Digit)> = vec![
-        ("cf", 1),
-        ("acf", 7),
-        ("bcdf", 4),
-        ("acdeg", 2),
-        ("acdfg", 3),
-        ("abdfg", 5),
-        ("abcefg", 0),
-        ("abdefg", 6),
-        ("abcdfg", 9),
-        ("abcdefg", 8),
-    ].into_iter().map(|(p, d)| (SegmentSet::from_str(p).unwrap(), d)).collect();
-
-    // Map of digits keyed by pattern.
-    pub static ref DIGITS_BY_SEGMENT_SET: HashMap<SegmentSet, Digit> = SEGMENT_SET_DIGIT_PAIRS
-        .iter()
-        .cloned()
-        .collect();
-
-    // Map of patterns keyed by their length.
-    pub static ref SEGMENT_SETS_BY_LENGTH: HashMap<usize, Vec<SegmentSet>> = SEGMENT_SET_DIGIT_PAIRS
-        .iter()
-        .fold(HashMap::new(), |mut m, (ss, _)| {
-            let patterns = m.entry(ss.len()).or_default();
-            patterns.push(ss.clone());
-            m
-        });
+struct Glyph {
+    segments: SegmentSet,
+    label: String,
 }
 
-struct Decoder {
-    mapping: SegmentMapping,
+// The segments of a display, which also name the wires connected to them, and the glyphs
+// it can show.
+struct DisplayDefinition {
+    segments: Vec<Segment>,
+    glyphs: Vec<Glyph>,
+    glyphs_by_segment_set: HashMap<SegmentSet, usize>,
 }
 
-impl Decoder {
+impl DisplayDefinition {
+    fn all(&self) -> SegmentSet {
+        SegmentSet::all(self.segments.len())
+    }
 
-    /// Building the decoder works by reducing a map of each incorrectly wired segment to all
-    /// possible segments (i.e., 1-to-many) to map of each incorrectly wired segment to the
-    /// correct corresponding segment (i.e., 1-to-1).
-    fn build(samples: &[SegmentSet]) -> Result<Decoder> {
-        // Create a vector of samples ordered by ascending length.
-        let mut samples = samples.to_vec();
-        samples.sort_unstable_by_key(|ss| ss.len());
+    fn pattern(&self, s: &str) -> Result<SegmentSet> {
+        let mut set = SegmentSet::default();
+        for c in s.chars() {
+            let index = self
+                .segments
+                .iter()
+                .position(|&segment| segment == c)
+                .with_context(|| format!("invalid segment: {}", c))?;
+            set = set.union(SegmentSet::single(index));
+        }
 
-        // Mapping of input segment to possible output segments. We'll aim to reduce the
-        // set of possible output segments to one for each input in the loop below.
-        let mut dsm: DivergentSegmentMapping = ALL_SEGMENTS
+        Ok(set)
+    }
+
+    fn format(&self, set: SegmentSet) -> String {
+        set.indices().map(|i| self.segments[i]).collect()
+    }
+
+    fn glyph(&self, set: SegmentSet) -> Option<&Glyph> {
+        self.glyphs_by_segment_set
+            .get(&set)
+            .map(|&i| &self.glyphs[i])
+    }
+
+    fn has_unique_length(&self, label: &str) -> bool {
+        self.glyphs
             .iter()
-            .map(|s| (*s, SegmentSet(ALL_SEGMENTS.clone())))
-            .collect();
+            .find(|g| g.label == label)
+            .is_some_and(|glyph| {
+                let len = glyph.segments.len();
+                self.glyphs
+                    .iter()
+                    .filter(|g| g.segments.len() == len)
+                    .count()
+                    == 1
+            })
+    }
 
-        for sample in &samples {
-            let possible_matches = SEGMENT_SETS_BY_LENGTH
-                .get(&sample.len())
-                .context(format!("invalid pattern: {}", sample))?;
+    // Whether every glyph is a single decimal digit, so outputs can be read as numbers.
+    fn is_numeric(&self) -> bool {
+        self.glyphs
+            .iter()
+            .all(|g| g.label.len() == 1 && g.label.chars().all(|c| c.is_ascii_digit()))
+    }
 
-            for possible_match in possible_matches {
-                let mut dsm_clone = dsm.clone();
+    // A definition can only ever be decoded if its glyphs identify the wiring uniquely,
+    // which is checked by decoding a display that shows every glyph through straight wires.
+    // When another wiring would show the same glyphs the two can never be told apart.
+    fn check(&self) -> Result<()> {
+        let samples = self.glyphs.iter().map(|g| g.segments).collect::<Vec<_>>();
+        let solutions = Decoder::solve(self, &samples, 2);
 
-                for sample_segment in sample.as_hashset() {
-                    let v = dsm_clone
-                        .get(sample_segment)
-                        .context(format!("invalid segment: {}", sample_segment))?;
+        if let Some(other) = solutions
+            .iter()
+            .find(|m| m.iter().enumerate().any(|(w, s)| w != *s))
+        {
+            let swaps = other
+                .iter()
+                .enumerate()
+                .filter(|(wire, segment)| wire != *segment)
+                .map(|(wire, &segment)| {
+                    format!("{}->{}", self.segments[wire], self.segments[segment])
+                })
+                .collect::<Vec<_>>();
+            bail!(
+                "display definition can never converge: wiring {} shows the same glyphs",
+                swaps.join(" ")
+            );
+        }
 
-                    let reduced_segment_set =
-                        SegmentSet(possible_match.as_hashset().intersection(v.as_hashset()).cloned().collect());
+        Ok(())
+    }
+}
 
-                    dsm_clone.insert(*sample_segment, reduced_segment_set);
-                }
+impl FromStr for DisplayDefinition {
+    type Err = anyhow::Error;
 
-                if Self::is_valid(&dsm_clone) {
-                    dsm = dsm_clone;
-                    break;
-                }
-            }
+    // Parses a definition of the form:
+    //
+    //   segments: abcdefg
+    //   0: abcefg
+    //   1: cf
+    //   ...
+    //
+    // where the first line lists the segments and each following line labels a glyph with the
+    // segments it lights. Blank lines and lines starting with '#' are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let segments = lines
+            .next()
+            .and_then(|line| line.strip_prefix("segments:"))
+            .context("display definition must start with its segments")?
+            .trim()
+            .chars()
+            .collect::<Vec<_>>();
+        ensure!(
+            !segments.is_empty() && segments.len() <= MAX_SEGMENTS,
+            "display must have between 1 and {} segments",
+            MAX_SEGMENTS
+        );
+        for (i, c) in segments.iter().enumerate() {
+            ensure!(!segments[..i].contains(c), "repeated segment: {}", c);
+            ensure!(*c != '|' && !c.is_whitespace(), "invalid segment: {:?}", c);
+        }
+
+        let mut display = DisplayDefinition {
+            segments,
+            glyphs: vec![],
+            glyphs_by_segment_set: HashMap::new(),
+        };
 
-            Self::reduce(&mut dsm);
+        for line in lines {
+            let (label, pattern) = line
+                .split_once(':')
+                .with_context(|| format!("invalid glyph: {}", line))?;
+            let label = label.trim().to_string();
+            let segments = display.pattern(pattern.trim())?;
 
-            if let Some(mapping) = Self::try_converge(&dsm) {
-                return Ok(Decoder { mapping });
+            if let Some(other) = display.glyph(segments) {
+                bail!(
+                    "glyphs {} and {} light the same segments",
+                    other.label,
+                    label
+                );
             }
+            ensure!(
+                display.glyphs.iter().all(|g| g.label != label),
+                "repeated glyph: {}",
+                label
+            );
+
+            display
+                .glyphs_by_segment_set
+                .insert(segments, display.glyphs.len());
+            display.glyphs.push(Glyph { segments, label });
+        }
+
+        ensure!(!display.glyphs.is_empty(), "display has no glyphs");
+        ensure!(
+            display.glyphs.len() <= MAX_GLYPHS,
+            "display must have at most {} glyphs",
+            MAX_GLYPHS
+        );
+
+        Ok(display)
+    }
+}
+
+// Maps each wire, by index, to the segment it is connected to.
+type SegmentMapping = Vec<usize>;
+
+// Maps each wire, by index, to the segments it could still be connected to.
+type Candidates = Vec<SegmentSet>;
+
+// The glyphs a sample could still be lighting, as a bitmask over the glyph indices.
+type GlyphSet = u128;
+
+struct Decoder<'a> {
+    display: &'a DisplayDefinition,
+    mapping: SegmentMapping,
+}
+
+impl<'a> Decoder<'a> {
+    /// Building the decoder works by narrowing down the segments that each wire could be
+    /// connected to until every wire has a single candidate. Each sample must light one of
+    /// the glyphs of the same length, so its wires can only be connected to segments lit by
+    /// one of those glyphs and the wires it doesn't use can only be connected to segments
+    /// left dark by one of them. Glyphs that no longer fit a sample's candidates are dropped
+    /// and the constraints are reapplied until nothing changes. Displays with symmetric
+    /// glyphs can stall before converging, in which case each candidate of the least
+    /// ambiguous wire is tried in turn.
+    fn build(
+        display: &'a DisplayDefinition,
+        samples: &[SegmentSet],
+    ) -> Result<Decoder<'a>, Diagnosis> {
+        let mut solutions = Self::solve(display, samples, 2);
+        if solutions.len() != 1 {
+            return Err(Diagnosis::new(display, samples, solutions.len()));
         }
 
-        bail!("could not converge signal patterns");
+        Ok(Decoder {
+            display,
+            mapping: solutions.remove(0),
+        })
     }
 
-    fn try_converge(dsm: &DivergentSegmentMapping) -> Option<SegmentMapping> {
-        let mut mapping = SegmentMapping::new();
-        for (s, ss) in dsm {
-            if ss.len() != 1 {
-                return None;
+    // Builds a decoder, and when the samples contradict each other tries flipping each
+    // segment of each sample. If the flips that make the samples identify a wiring all
+    // identify the same one, the flipped sample is taken to be the one that was garbled.
+    fn build_repaired(
+        display: &'a DisplayDefinition,
+        samples: &[SegmentSet],
+    ) -> Result<(Decoder<'a>, Option<Repair>), Diagnosis> {
+        let diagnosis = match Self::build(display, samples) {
+            Ok(decoder) => return Ok((decoder, None)),
+            Err(diagnosis) => diagnosis,
+        };
+        if !matches!(diagnosis.failure, Failure::Contradictory) {
+            return Err(diagnosis);
+        }
+
+        let mut fixes = vec![];
+        for (i, sample) in samples.iter().enumerate() {
+            if samples[..i].contains(sample) {
+                continue;
+            }
+
+            for segment in display.all().indices() {
+                let mut flipped = samples.to_vec();
+                for s in flipped.iter_mut().filter(|s| *s == sample) {
+                    *s = sample.symmetric_difference(SegmentSet::single(segment));
+                }
+
+                if let [mapping] = &Self::solve(display, &flipped, 2)[..] {
+                    fixes.push((*sample, flipped[i], mapping.clone()));
+                }
             }
-            mapping.insert(*s, *ss.as_hashset().iter().next().unwrap());
         }
 
-        Some(mapping)
+        // If flips lead to different wirings there's no telling which sample was garbled.
+        let (original, repaired, mapping) = match fixes.first() {
+            Some(fix) if fixes.iter().all(|(_, _, m)| *m == fix.2) => fix.clone(),
+            _ => return Err(diagnosis),
+        };
+
+        Ok((
+            Decoder { display, mapping },
+            Some(Repair {
+                kind: "sample",
+                original: display.format(original),
+                repaired: display.format(repaired),
+            }),
+        ))
     }
 
-    fn reduce(dsm: &mut DivergentSegmentMapping) {
-        while Self::reduce_once(dsm) {}
+    // Finds up to `limit` wirings under which every sample lights a glyph.
+    fn solve(
+        display: &DisplayDefinition,
+        samples: &[SegmentSet],
+        limit: usize,
+    ) -> Vec<SegmentMapping> {
+        let (candidates, matches) = Self::initial(display, samples);
+
+        let mut solutions = vec![];
+        Self::search(display, samples, candidates, matches, limit, &mut solutions);
+
+        solutions
     }
 
-    fn reduce_once(dsm: &mut DivergentSegmentMapping) -> bool {
-        let mut semi_converged: HashSet<SegmentSet> = HashSet::new();
-        for possible_matches in dsm.values() {
-            let count = dsm.values().filter(|v| *v == possible_matches).count();
-            if count == possible_matches.len() {
-                semi_converged.insert(possible_matches.clone());
+    // Every wire starts out possibly connected to any segment and every sample possibly
+    // lighting any glyph of the same length.
+    fn initial(display: &DisplayDefinition, samples: &[SegmentSet]) -> (Candidates, Vec<GlyphSet>) {
+        let candidates = vec![display.all(); display.segments.len()];
+
+        let matches = samples
+            .iter()
+            .map(|sample| {
+                display
+                    .glyphs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, g)| g.segments.len() == sample.len())
+                    .fold(0, |acc: GlyphSet, (i, _)| acc | 1 << i)
+            })
+            .collect();
+
+        (candidates, matches)
+    }
+
+    fn search(
+        display: &DisplayDefinition,
+        samples: &[SegmentSet],
+        mut candidates: Candidates,
+        mut matches: Vec<GlyphSet>,
+        limit: usize,
+        solutions: &mut Vec<SegmentMapping>,
+    ) {
+        if Self::propagate(display, samples, &mut candidates, &mut matches).is_err() {
+            return;
+        }
+
+        let ambiguous = (0..candidates.len())
+            .filter(|&wire| candidates[wire].len() > 1)
+            .min_by_key(|&wire| candidates[wire].len());
+
+        let wire = match ambiguous {
+            Some(wire) => wire,
+            None => {
+                let mapping = candidates.iter().filter_map(|c| c.first()).collect();
+                if Self::lights_glyphs(display, samples, &mapping) {
+                    solutions.push(mapping);
+                }
+                return;
             }
+        };
+
+        for segment in candidates[wire].indices() {
+            if solutions.len() >= limit {
+                break;
+            }
+
+            let mut guess = candidates.clone();
+            guess[wire] = SegmentSet::single(segment);
+            Self::search(display, samples, guess, matches.clone(), limit, solutions);
         }
+    }
+
+    // Applies the sample constraints until nothing changes, failing if they contradict.
+    fn propagate(
+        display: &DisplayDefinition,
+        samples: &[SegmentSet],
+        candidates: &mut Candidates,
+        matches: &mut [GlyphSet],
+    ) -> Result<()> {
+        let all = display.all();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
 
+            for (sample, glyphs) in samples.iter().zip(matches.iter_mut()) {
+                let mut lit = SegmentSet::default();
+                let mut dark = SegmentSet::default();
+                for (i, glyph) in display.glyphs.iter().enumerate() {
+                    if *glyphs & 1 << i == 0 {
+                        continue;
+                    }
+                    if Self::fits(candidates, all, *sample, glyph.segments) {
+                        lit = lit.union(glyph.segments);
+                        dark = dark.union(all.difference(glyph.segments));
+                    } else {
+                        *glyphs &= !(1 << i);
+                    }
+                }
+                if *glyphs == 0 {
+                    bail!("no glyph matches pattern: {}", display.format(*sample));
+                }
+
+                for (wire, candidate) in candidates.iter_mut().enumerate() {
+                    let allowed = if sample.contains(wire) { lit } else { dark };
+                    let reduced = candidate.intersection(allowed);
+                    if reduced != *candidate {
+                        *candidate = reduced;
+                        changed = true;
+                    }
+                }
+            }
+
+            changed |= Self::reduce(candidates)?;
+        }
+
+        Ok(())
+    }
+
+    // Whether a sample could light a glyph given the current candidates for each wire.
+    fn fits(
+        candidates: &Candidates,
+        all: SegmentSet,
+        sample: SegmentSet,
+        glyph: SegmentSet,
+    ) -> bool {
+        candidates.iter().enumerate().all(|(wire, candidate)| {
+            let allowed = if sample.contains(wire) {
+                glyph
+            } else {
+                all.difference(glyph)
+            };
+            !candidate.intersection(allowed).is_empty()
+        })
+    }
+
+    // When n wires share the same n candidate segments those segments must be taken by
+    // them, so they are removed from the candidates of every other wire. Returns whether
+    // any candidates changed.
+    fn reduce(candidates: &mut Candidates) -> Result<bool> {
         let mut modified = false;
-        for possible_matches in dsm.values_mut() {
-            if semi_converged.contains(possible_matches) {
+        for wire in 0..candidates.len() {
+            let group = candidates[wire];
+            let sharing = candidates.iter().filter(|c| **c == group).count();
+            if sharing > group.len() {
+                bail!("wires cannot share candidate segments");
+            }
+            if sharing < group.len() {
                 continue;
             }
 
-            for semi_converged_set in &semi_converged {
-                let reduced_matches = SegmentSet(possible_matches
-                    .as_hashset()
-                    .difference(semi_converged_set.as_hashset())
-                    .cloned()
-                    .collect());
-
-                if *possible_matches != reduced_matches {
-                    *possible_matches = reduced_matches;
+            for candidate in candidates.iter_mut() {
+                if *candidate != group && !candidate.intersection(group).is_empty() {
+                    *candidate = candidate.difference(group);
                     modified = true;
                 }
             }
         }
 
-        modified
+        Ok(modified)
     }
 
-    fn is_valid(dsm: &DivergentSegmentMapping) -> bool {
-        for possible_matches in dsm.values() {
-            if possible_matches.is_empty() {
-                return false;
-            }
+    fn lights_glyphs(
+        display: &DisplayDefinition,
+        samples: &[SegmentSet],
+        mapping: &SegmentMapping,
+    ) -> bool {
+        samples
+            .iter()
+            .all(|sample| display.glyph(Self::rewire(mapping, *sample)).is_some())
+    }
 
-            if possible_matches.len() == 1 {
-                let count = dsm.values().filter(|v| *v == possible_matches).count();
-                if count > 1 {
-                    return false;
-                }
+    fn rewire(mapping: &SegmentMapping, set: SegmentSet) -> SegmentSet {
+        set.indices().fold(SegmentSet::default(), |acc, wire| {
+            acc.union(SegmentSet::single(mapping[wire]))
+        })
+    }
+
+    fn decode(&self, encoded_segment_set: &SegmentSet) -> Result<&'a str> {
+        let decoded_segment_set = Self::rewire(&self.mapping, *encoded_segment_set);
+
+        self.display
+            .glyph(decoded_segment_set)
+            .map(|g| g.label.as_str())
+            .with_context(|| {
+                format!(
+                    "could not decode pattern: {} (lights {}, which is not a glyph)",
+                    self.display.format(*encoded_segment_set),
+                    self.display.format(decoded_segment_set)
+                )
+            })
+    }
+
+    // Decodes a pattern, and if it doesn't light a glyph decodes it as the only glyph one
+    // flipped segment away, if there is one.
+    fn decode_nearest(
+        &self,
+        encoded_segment_set: &SegmentSet,
+    ) -> Result<(&'a str, Option<Repair>)> {
+        if let Ok(label) = self.decode(encoded_segment_set) {
+            return Ok((label, None));
+        }
+
+        let nearest = self
+            .display
+            .all()
+            .indices()
+            .map(|wire| encoded_segment_set.symmetric_difference(SegmentSet::single(wire)))
+            .filter_map(|flipped| self.decode(&flipped).ok().map(|label| (flipped, label)))
+            .collect::<Vec<_>>();
+
+        match nearest[..] {
+            [(repaired, label)] => Ok((
+                label,
+                Some(Repair {
+                    kind: "output",
+                    original: self.display.format(*encoded_segment_set),
+                    repaired: format!("{} ({})", self.display.format(repaired), label),
+                }),
+            )),
+            [] => self.decode(encoded_segment_set).map(|label| (label, None)),
+            _ => bail!(
+                "could not decode pattern: {} (one segment away from glyphs {})",
+                self.display.format(*encoded_segment_set),
+                nearest
+                    .iter()
+                    .map(|(_, label)| *label)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Failure {
+    // No wiring lights a glyph for every sample.
+    Contradictory,
+    // More than one wiring lights a glyph for every sample.
+    UnderDetermined,
+}
+
+// Explains why the wiring of an entry could not be decoded.
+#[derive(Debug)]
+struct Diagnosis {
+    failure: Failure,
+    segments: Vec<Segment>,
+    // The segments each wire could be connected to once the sample constraints have been
+    // applied, up to the point they contradict.
+    candidates: Candidates,
+    // A smallest set of samples that contradict each other.
+    conflicts: Vec<SegmentSet>,
+}
+
+impl Diagnosis {
+    fn new(display: &DisplayDefinition, samples: &[SegmentSet], solutions: usize) -> Self {
+        let (mut candidates, mut matches) = Decoder::initial(display, samples);
+        let _ = Decoder::propagate(display, samples, &mut candidates, &mut matches);
+
+        let (failure, conflicts) = if solutions == 0 {
+            (Failure::Contradictory, Self::conflicts(display, samples))
+        } else {
+            (Failure::UnderDetermined, vec![])
+        };
+
+        Diagnosis {
+            failure,
+            segments: display.segments.clone(),
+            candidates,
+            conflicts,
+        }
+    }
+
+    // Drops every sample that isn't needed for the rest to contradict each other.
+    fn conflicts(display: &DisplayDefinition, samples: &[SegmentSet]) -> Vec<SegmentSet> {
+        let mut conflicts = samples.to_vec();
+        let mut i = 0;
+        while i < conflicts.len() {
+            let mut without = conflicts.clone();
+            without.remove(i);
+            if Decoder::solve(display, &without, 1).is_empty() {
+                conflicts = without;
+            } else {
+                i += 1;
             }
         }
 
-        true
+        conflicts
     }
 
-    fn decode(&self, encoded_segment_set: &SegmentSet) -> Result<Digit> {
-        let mut decoded_segment_set = HashSet::new();
-        for segment in encoded_segment_set.as_hashset() {
-            let decoded_segment = self
-                .mapping
-                .get(segment)
-                .context(format!("invalid segment: {}", segment))?;
+    fn format(&self, set: SegmentSet) -> String {
+        set.indices().map(|i| self.segments[i]).collect()
+    }
+}
 
-            decoded_segment_set.insert(*decoded_segment);
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.failure {
+            Failure::Contradictory => {
+                writeln!(
+                    f,
+                    "contradictory signal patterns: no wiring fits every sample"
+                )?;
+                let conflicts = self
+                    .conflicts
+                    .iter()
+                    .map(|s| self.format(*s))
+                    .collect::<Vec<_>>();
+                writeln!(f, "  conflicting samples: {}", conflicts.join(" "))?;
+            }
+            Failure::UnderDetermined => {
+                writeln!(
+                    f,
+                    "under-determined signal patterns: more than one wiring fits every sample"
+                )?;
+            }
         }
 
-        let decoded_segment_set = SegmentSet(decoded_segment_set);
+        let candidates = self
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(wire, c)| {
+                let c = if c.is_empty() {
+                    "none".to_string()
+                } else {
+                    self.format(*c)
+                };
+                format!("{}->{}", self.segments[wire], c)
+            })
+            .collect::<Vec<_>>();
+        write!(f, "  candidates: {}", candidates.join(" "))
+    }
+}
+
+impl std::error::Error for Diagnosis {}
 
-        DIGITS_BY_SEGMENT_SET
-            .get(&decoded_segment_set)
-            .cloned()
-            .context(format!("could not decode pattern: {}", encoded_segment_set))
+// A pattern that was decoded as if one of its segments had been flipped.
+struct Repair {
+    kind: &'static str,
+    original: String,
+    repaired: String,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "repaired {} {} to {}",
+            self.kind, self.original, self.repaired
+        )
+    }
+}
+
+// Scrambles glyphs the way a miswired display would show them.
+struct Encoder<'a> {
+    display: &'a DisplayDefinition,
+    // The wire connected to each segment, by index.
+    wires: SegmentMapping,
+}
+
+impl<'a> Encoder<'a> {
+    // Takes the wire connected to each segment, in the order of the display segments.
+    fn new(display: &'a DisplayDefinition, wires: &str) -> Result<Self> {
+        let wires = wires
+            .chars()
+            .map(|c| {
+                display
+                    .pattern(&c.to_string())?
+                    .first()
+                    .context("missing wire")
+            })
+            .collect::<Result<SegmentMapping>>()?;
+
+        let mut sorted = wires.clone();
+        sorted.sort_unstable();
+        ensure!(
+            sorted.iter().copied().eq(0..display.segments.len()),
+            "wires must connect each segment to a different wire"
+        );
+
+        Ok(Encoder { display, wires })
+    }
+
+    fn scramble(&self, set: SegmentSet) -> SegmentSet {
+        set.indices().fold(SegmentSet::default(), |acc, segment| {
+            acc.union(SegmentSet::single(self.wires[segment]))
+        })
+    }
+
+    // Produces an entry whose samples show every glyph and whose outputs show the glyphs
+    // with the given labels.
+    fn encode(&self, labels: &[&str]) -> Result<Entry> {
+        let samples = self
+            .display
+            .glyphs
+            .iter()
+            .map(|g| self.scramble(g.segments))
+            .collect();
+
+        let outputs = labels
+            .iter()
+            .map(|label| {
+                self.display
+                    .glyphs
+                    .iter()
+                    .find(|g| g.label == *label)
+                    .map(|g| self.scramble(g.segments))
+                    .with_context(|| format!("unknown glyph: {}", label))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Entry { samples, outputs })
+    }
+}
+
+impl fmt::Display for Encoder<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &wire in &self.wires {
+            write!(f, "{}", self.display.segments[wire])?;
+        }
+
+        Ok(())
     }
 }
 
@@ -290,14 +994,34 @@ struct Entry {
     outputs: Vec<SegmentSet>,
 }
 
-impl FromStr for Entry {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Entry {
+    fn parse(s: &str, display: &DisplayDefinition) -> Result<Self> {
         let (inputs, outputs) = s.split_once(" | ").context("bad input")?;
-        let samples = inputs.split_terminator(' ').map(SegmentSet::from_str).collect::<Result<_>>()?;
-        let outputs = outputs.split_terminator(' ').map(SegmentSet::from_str).collect::<Result<_>>()?;
+        let samples = inputs
+            .split_terminator(' ')
+            .map(|p| display.pattern(p))
+            .collect::<Result<_>>()?;
+        let outputs = outputs
+            .split_terminator(' ')
+            .map(|p| display.pattern(p))
+            .collect::<Result<_>>()?;
 
         Ok(Entry { samples, outputs })
     }
+
+    // Formats the entry as a line of the puzzle input.
+    fn format(&self, display: &DisplayDefinition) -> String {
+        let samples = self
+            .samples
+            .iter()
+            .map(|s| display.format(*s))
+            .collect::<Vec<_>>();
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|s| display.format(*s))
+            .collect::<Vec<_>>();
+
+        format!("{} | {}", samples.join(" "), outputs.join(" "))
+    }
 }
@@ -1,6 +1,8 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use std::{
+    cmp::Reverse,
     collections::{HashMap, HashSet},
+    env, fmt,
     io::{self, Read},
     ops::{Deref, DerefMut},
     str::FromStr,
@@ -10,35 +12,105 @@ fn main() -> Result<()> {
     let mut input = String::new();
     io::stdin().read_to_string(&mut input)?;
 
-    let graph: HeightGraph = input.parse()?;
+    // Optional arguments:
+    //   --connectivity N    join cells into basins through their 4 (the default) or 8
+    //                       neighbours
+    //   --basin X,Y         print the basin that the point belongs to
+    //   --flood MIN-MAX     raise the water level from MIN to MAX, printing the basins and
+    //                       the basins that merge at MIN and at each level that covers
+    //                       more cells
+    //   --delimiter D       separate the heights on each line with D, which is "none" (one
+    //                       digit per cell, the default), "whitespace" or a single character
+    //   --no-data MARKER    treat cells holding MARKER as walls with no height
+    //   --max-height H      treat cells at height H or above as the walls between basins
+    //                       rather than 9
+    let mut format = HeightMapFormat::default();
+    let mut max_height = HeightGraph::MAX_HEIGHT;
+    let mut connectivity = Connectivity::Four;
+    let mut queries = vec![];
+    let mut flood = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--connectivity" => {
+                connectivity = args.next().context("missing connectivity")?.parse()?
+            }
+            "--basin" => queries.push(args.next().context("missing point")?.parse::<Point>()?),
+            "--flood" => {
+                let range = args.next().context("missing water levels")?;
+                let (min, max) = range
+                    .split_once('-')
+                    .context("water levels must be MIN-MAX")?;
+                flood = Some((min.parse()?, max.parse()?));
+            }
+            "--delimiter" => {
+                format.delimiter = args.next().context("missing delimiter")?.parse()?
+            }
+            "--no-data" => format.no_data = Some(args.next().context("missing no-data marker")?),
+            "--max-height" => max_height = args.next().context("missing height")?.parse()?,
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    let mut graph = HeightGraph::parse(&input, &format)?;
+    graph.max_height = max_height;
 
     part1(&graph);
-    part2(&graph);
+    part2(&graph, connectivity);
+
+    if !queries.is_empty() {
+        let labels = graph.label_basins(connectivity);
+        for p in queries {
+            match labels.basin_of(&p) {
+                Some(id) => println!("Basin of {}: {} (size {})", p, id, labels.sizes[id]),
+                None => println!("Basin of {}: none", p),
+            }
+        }
+    }
+
+    if let Some((min, max)) = flood {
+        for level in graph.flood(min, max, connectivity) {
+            level.print();
+        }
+    }
 
     Ok(())
 }
 
 fn part1(graph: &HeightGraph) {
-    let total_risk: u32 = graph.low_points().iter().map(|p| graph[p] as u32 + 1).sum();
+    let total_risk: u64 = graph.low_points().iter().map(|p| graph[p] as u64 + 1).sum();
     println!("Part 1 answer: {}", total_risk);
 }
 
-fn part2(graph: &HeightGraph) {
-    let basin_multiple: u32 = graph
-        .basins()
+fn part2(graph: &HeightGraph, connectivity: Connectivity) {
+    // Three basins of a few million cells each already overflow a u64.
+    let basin_multiple: u128 = graph
+        .basins(connectivity)
         .iter()
         .take(3)
-        .fold(1, |acc, b| acc * b.len() as u32);
+        .map(|b| b.len() as u128)
+        .product();
     println!("Part 2 answer: {}", basin_multiple);
 }
 
-struct HeightGraph(HashMap<Point, u8>);
+type Height = u16;
+
+// The heights of the cells of a height map. Cells with no data are left out, so they act
+// as walls that are never low points and never part of a basin.
+struct HeightGraph {
+    heights: HashMap<Point, Height>,
+    // Cells at or above this height are the walls between basins.
+    max_height: Height,
+}
 
 impl HeightGraph {
-    const MAX_HEIGHT: u8 = 9;
+    const MAX_HEIGHT: Height = 9;
 
     fn new() -> Self {
-        Self(HashMap::new())
+        Self {
+            heights: HashMap::new(),
+            max_height: Self::MAX_HEIGHT,
+        }
     }
 
     fn low_points(&self) -> HashSet<Point> {
@@ -59,54 +131,297 @@ impl HeightGraph {
         points
     }
 
-    fn basins(&self) -> Vec<HashSet<Point>> {
-        let mut basins = vec![];
-        for p in self.low_points() {
-            let mut basin = HashSet::new();
-            self.walk_basin(p, &mut basin);
+    fn basins(&self, connectivity: Connectivity) -> Vec<HashSet<Point>> {
+        let labels = self.label_basins(connectivity);
 
-            basins.push(basin)
+        let mut basins = vec![HashSet::new(); labels.sizes.len()];
+        for (p, id) in &labels.ids {
+            basins[*id].insert(*p);
         }
 
-        basins.sort_unstable_by_key(|b| -(b.len() as i32));
+        basins.sort_unstable_by_key(|b| Reverse(b.len()));
 
         basins
     }
 
-    fn walk_basin(&self, point: Point, acc: &mut HashSet<Point>) {
-        match self.get(&point) {
-            Some(v) if *v < Self::MAX_HEIGHT => acc.insert(point),
-            _ => return,
+    // Labels every cell below the maximum height with the id of its basin by flood filling
+    // from each unlabelled cell in turn. Ids are assigned in reading order of the first cell
+    // of each basin.
+    fn label_basins(&self, connectivity: Connectivity) -> BasinLabels {
+        let mut points = self.keys().copied().collect::<Vec<_>>();
+        points.sort_unstable_by_key(|p| (p.y, p.x));
+
+        let mut labels = BasinLabels {
+            ids: HashMap::new(),
+            sizes: vec![],
+        };
+
+        let mut stack = vec![];
+        for start in points {
+            if !self.in_basin(&start) || labels.ids.contains_key(&start) {
+                continue;
+            }
+
+            let id = labels.sizes.len();
+            let mut size = 0;
+            labels.ids.insert(start, id);
+            stack.push(start);
+
+            while let Some(point) = stack.pop() {
+                size += 1;
+                for p in point.neighbours(connectivity) {
+                    if self.in_basin(&p) && !labels.ids.contains_key(&p) {
+                        labels.ids.insert(p, id);
+                        stack.push(p);
+                    }
+                }
+            }
+
+            labels.sizes.push(size);
+        }
+
+        labels
+    }
+
+    fn in_basin(&self, point: &Point) -> bool {
+        matches!(self.get(point), Some(v) if *v < self.max_height)
+    }
+
+    // Raises the water level from `min` to `max`, where the water at a level covers the
+    // cells lower than it, and records the basins that the covered cells form at `min` and
+    // at each level that covers more cells than the one below it. Cells are added to a
+    // union-find structure in order of height, and a basin is named after its lowest cell,
+    // which is kept when it merges with a shallower basin.
+    fn flood(&self, min: u32, max: u32, connectivity: Connectivity) -> Vec<WaterLevel> {
+        let mut points = self.keys().copied().collect::<Vec<_>>();
+        points.sort_unstable_by_key(|p| (self[p], p.y, p.x));
+        let index = points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (*p, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut basins = UnionFind::new(points.len());
+        let mut covered = vec![false; points.len()];
+        let mut roots = HashSet::new();
+        let mut next = 0;
+
+        // Only the levels one above the height of some cell cover more cells, and the level
+        // below `min` is visited too so that merges are reported from there.
+        let highest = points.last().map_or(0, |p| self[p] as u32 + 1);
+        let mut steps = points
+            .iter()
+            .map(|p| self[p] as u32 + 1)
+            .filter(|&level| level > min && level <= max.min(highest))
+            .collect::<Vec<_>>();
+        steps.dedup();
+        if min <= max {
+            steps.splice(0..0, min.checked_sub(1).into_iter().chain([min]));
+        }
+
+        let mut levels = vec![];
+        for level in steps {
+            // Basins named after cells covered at an earlier level existed before this one.
+            let existing = next;
+            let mut merges = vec![];
+
+            while next < points.len() && (self[&points[next]] as u32) < level {
+                covered[next] = true;
+                roots.insert(next);
+
+                for p in points[next].neighbours(connectivity) {
+                    let neighbour = match index.get(&p) {
+                        Some(&i) if covered[i] => i,
+                        _ => continue,
+                    };
+
+                    let (a, b) = (basins.find(next), basins.find(neighbour));
+                    if a == b {
+                        continue;
+                    }
+
+                    let (origin_a, origin_b) = (basins.origin[a], basins.origin[b]);
+                    let root = basins.union(a, b);
+                    roots.remove(&a);
+                    roots.remove(&b);
+                    roots.insert(root);
+
+                    if origin_a < existing && origin_b < existing {
+                        merges.push(Merge {
+                            basin: points[origin_a.min(origin_b)],
+                            absorbed: points[origin_a.max(origin_b)],
+                        });
+                    }
+                }
+
+                next += 1;
+            }
+
+            if level < min {
+                continue;
+            }
+
+            let mut sizes = roots
+                .iter()
+                .map(|&r| (basins.size[r], points[basins.origin[r]]))
+                .collect::<Vec<_>>();
+            sizes.sort_unstable_by_key(|(size, p)| (Reverse(*size), p.y, p.x));
+
+            levels.push(WaterLevel {
+                level,
+                basins: sizes,
+                merges,
+            });
+        }
+
+        levels
+    }
+}
+
+// The basin that each cell belongs to, and the number of cells in each basin.
+struct BasinLabels {
+    ids: HashMap<Point, usize>,
+    sizes: Vec<usize>,
+}
+
+impl BasinLabels {
+    fn basin_of(&self, point: &Point) -> Option<usize> {
+        self.ids.get(point).copied()
+    }
+}
+
+// Disjoint sets of cells, by index, that also track the size and the first cell (in the
+// order that cells were added) of each set.
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+    origin: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        UnionFind {
+            parent: (0..n).collect(),
+            size: vec![1; n],
+            origin: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+
+        i
+    }
+
+    // Joins the sets with the given roots, returning the root of the joined set.
+    fn union(&mut self, a: usize, b: usize) -> usize {
+        let (root, child) = if self.size[a] >= self.size[b] {
+            (a, b)
+        } else {
+            (b, a)
         };
 
-        for p in point.adjacent().difference(&acc.clone()) {
-            self.walk_basin(*p, acc);
+        self.parent[child] = root;
+        self.size[root] += self.size[child];
+        self.origin[root] = self.origin[root].min(self.origin[child]);
+
+        root
+    }
+}
+
+// The basins under the water at a given level, as their sizes and lowest cells ordered by
+// descending size, and the basins that merged as the water rose to it.
+struct WaterLevel {
+    level: u32,
+    basins: Vec<(usize, Point)>,
+    merges: Vec<Merge>,
+}
+
+impl WaterLevel {
+    fn print(&self) {
+        let sizes = self
+            .basins
+            .iter()
+            .map(|(size, _)| size.to_string())
+            .collect::<Vec<_>>();
+        if sizes.is_empty() {
+            println!("Water level {}: no basins", self.level);
+        } else {
+            println!(
+                "Water level {}: {} basin(s), sizes {}",
+                self.level,
+                sizes.len(),
+                sizes.join(" ")
+            );
+        }
+
+        for merge in &self.merges {
+            println!(
+                "  basin at {} merged into basin at {}",
+                merge.absorbed, merge.basin
+            );
+        }
+    }
+}
+
+struct Merge {
+    basin: Point,
+    absorbed: Point,
+}
+
+#[derive(Clone, Copy)]
+enum Connectivity {
+    // Cells are joined to the cells above, below, left and right of them.
+    Four,
+    // Cells are also joined to their diagonal neighbours.
+    Eight,
+}
+
+impl FromStr for Connectivity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" => Ok(Connectivity::Four),
+            "8" => Ok(Connectivity::Eight),
+            _ => bail!("connectivity must be 4 or 8: {}", s),
         }
     }
 }
 
 impl Deref for HeightGraph {
-    type Target = HashMap<Point, u8>;
+    type Target = HashMap<Point, Height>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.heights
     }
 }
 
 impl DerefMut for HeightGraph {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.heights
     }
 }
 
-impl FromStr for HeightGraph {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl HeightGraph {
+    fn parse(s: &str, format: &HeightMapFormat) -> Result<Self> {
         let mut graph = HeightGraph::new();
         for (y, line) in s.lines().enumerate() {
-            for (x, ch) in line.chars().enumerate() {
-                let v: u8 = ch.to_string().parse()?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            for (x, cell) in format.cells(line).enumerate() {
+                if format.no_data.as_deref() == Some(cell) {
+                    continue;
+                }
+
+                let v: Height = cell.parse().with_context(|| {
+                    format!("line {}, cell {}: invalid height '{}'", y + 1, x + 1, cell)
+                })?;
                 graph.insert(Point::new(x as i32, y as i32), v);
             }
         }
@@ -115,6 +430,49 @@ impl FromStr for HeightGraph {
     }
 }
 
+#[derive(Default)]
+struct HeightMapFormat {
+    delimiter: Delimiter,
+    // The text of cells that have no data.
+    no_data: Option<String>,
+}
+
+impl HeightMapFormat {
+    fn cells<'a>(&self, line: &'a str) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        match self.delimiter {
+            Delimiter::None => Box::new(
+                line.char_indices()
+                    .map(move |(i, c)| &line[i..i + c.len_utf8()]),
+            ),
+            Delimiter::Whitespace => Box::new(line.split_whitespace()),
+            Delimiter::Char(d) => Box::new(line.split(d).map(str::trim)),
+        }
+    }
+}
+
+#[derive(Default)]
+enum Delimiter {
+    // Each character is a cell.
+    #[default]
+    None,
+    Whitespace,
+    Char(char),
+}
+
+impl FromStr for Delimiter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (s, chars.next(), chars.next()) {
+            ("none", _, _) => Ok(Delimiter::None),
+            ("whitespace", _, _) => Ok(Delimiter::Whitespace),
+            (_, Some(c), None) => Ok(Delimiter::Char(c)),
+            _ => bail!("invalid delimiter: {}", s),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Point {
     x: i32,
@@ -159,4 +517,33 @@ impl Point {
             .into_iter()
             .collect()
     }
+
+    fn neighbours(&self, connectivity: Connectivity) -> Vec<Point> {
+        let mut neighbours = vec![self.above(), self.below(), self.left(), self.right()];
+        if let Connectivity::Eight = connectivity {
+            neighbours.extend([
+                self.above().left(),
+                self.above().right(),
+                self.below().left(),
+                self.below().right(),
+            ]);
+        }
+
+        neighbours
+    }
+}
+
+impl FromStr for Point {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s.split_once(',').context("point must be X,Y")?;
+        Ok(Point::new(x.trim().parse()?, y.trim().parse()?))
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
 }
@@ -1,7 +1,8 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use lazy_static::lazy_static;
 use std::{
     collections::HashMap,
+    env, fmt,
     io::{self, Read},
     ops::Deref,
     ops::DerefMut,
@@ -12,21 +13,52 @@ fn main() -> Result<()> {
     let mut input = String::new();
     io::stdin().read_to_string(&mut input)?;
 
+    // Optional arguments: "--lint" reports every error in every line, and a summary of
+    // the whole file, and "--complete" prints every line that isn't corrupted with the
+    // closing chars it is missing added.
+    let mut show_lint = false;
+    let mut show_completed = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--lint" => show_lint = true,
+            "--complete" => show_completed = true,
+            _ => bail!("unknown argument: {}", arg),
+        }
+    }
+
     part1(&input)?;
     part2(&input)?;
 
+    if show_lint {
+        let lints = input.lines().map(lint).collect::<Vec<_>>();
+        for (i, lint) in lints.iter().enumerate() {
+            if !lint.is_clean() {
+                println!("line {}: {}", i + 1, lint);
+                for error in &lint.errors {
+                    println!("  {}", error);
+                }
+            }
+        }
+
+        println!("{}", LintSummary::new(&lints));
+    }
+
+    if show_completed {
+        for line in input.lines() {
+            if let Ok(chunks) = ChunkVec::parse(line) {
+                println!("{}{}", line, chunks.completion());
+            }
+        }
+    }
+
     Ok(())
 }
 
 fn part1(input: &str) -> Result<()> {
     let mut points = 0;
     for line in input.lines() {
-        let mut chunks = ChunkVec::new();
-        for ch in line.chars() {
-            if chunks.consume(ch).is_err() {
-                points += ILLEGAL_CHAR_POINTS[&ch];
-                break;
-            }
+        if let Some(error) = lint(line).errors.first() {
+            points += error.points();
         }
     }
 
@@ -37,25 +69,10 @@ fn part1(input: &str) -> Result<()> {
 
 fn part2(input: &str) -> Result<()> {
     let mut line_points = vec![];
-    'line: for line in input.lines() {
-        let mut points = 0_u64;
-        let mut chunks = ChunkVec::new();
-        for ch in line.chars() {
-            if chunks.consume(ch).is_err() {
-                continue 'line;
-            }
-        }
-
-        let mut closing_char = '}';
-        while !chunks.is_complete() {
-            match chunks.consume(closing_char) {
-                Ok(_) => points = points * 5 + LEGAL_CHAR_POINTS[&closing_char] as u64,
-                Err(ChunkError::ExpectedClosingChar(ch)) => closing_char = ch,
-                e @ Err(_) => e?,
-            }
+    for line in input.lines() {
+        if let Ok(chunks) = ChunkVec::parse(line) {
+            line_points.push(completion_score(&chunks.completion()));
         }
-
-        line_points.push(points);
     }
 
     line_points.sort_unstable();
@@ -88,6 +105,139 @@ lazy_static! {
             .collect();
 }
 
+// The autocomplete score of the closing chars that complete a line.
+fn completion_score(completion: &str) -> u64 {
+    completion
+        .chars()
+        .fold(0, |points, ch| points * 5 + LEGAL_CHAR_POINTS[&ch] as u64)
+}
+
+// Parses a line in full, recovering from each error to find the ones after it. A wrong
+// closing char is taken to be a missing closing char if it closes the chunk enclosing the
+// innermost open chunk, so both are closed, and otherwise to be a mistyped closing char for
+// the innermost open chunk. Any other char that can't be consumed is skipped.
+fn lint(line: &str) -> LineLint {
+    let mut chunks = ChunkVec::new();
+    let mut errors = vec![];
+    for (i, ch) in line.chars().enumerate() {
+        if let Err(error) = chunks.consume(ch) {
+            if let ChunkError::ExpectedClosingChar(_) = error {
+                let awaiting = chunks.awaiting();
+                let depth = awaiting.len() - 1;
+                if depth > 0 && awaiting[depth - 1] == ch {
+                    chunks.close_from(depth - 1);
+                } else {
+                    chunks.close_from(depth);
+                }
+            }
+
+            errors.push(LintError {
+                column: i + 1,
+                found: ch,
+                error,
+            });
+        }
+    }
+
+    LineLint {
+        errors,
+        unclosed: chunks.awaiting().len(),
+    }
+}
+
+struct LintError {
+    column: usize,
+    found: char,
+    error: ChunkError,
+}
+
+impl LintError {
+    // The syntax error score of the char.
+    fn points(&self) -> u32 {
+        ILLEGAL_CHAR_POINTS.get(&self.found).copied().unwrap_or(0)
+    }
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "column {}: found {}, {}",
+            self.column, self.found, self.error
+        )
+    }
+}
+
+// The errors in a line and the number of chunks left open at the end of it.
+struct LineLint {
+    errors: Vec<LintError>,
+    unclosed: usize,
+}
+
+impl LineLint {
+    fn is_clean(&self) -> bool {
+        self.errors.is_empty() && self.unclosed == 0
+    }
+}
+
+impl fmt::Display for LineLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.errors.is_empty() {
+            write!(f, "corrupted, {} error(s)", self.errors.len())?;
+        } else if self.unclosed > 0 {
+            write!(f, "incomplete")?;
+        } else {
+            write!(f, "ok")?;
+        }
+
+        if self.unclosed > 0 {
+            write!(f, ", {} chunk(s) left open", self.unclosed)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct LintSummary {
+    lines: usize,
+    clean: usize,
+    corrupted: usize,
+    incomplete: usize,
+    errors: usize,
+    // The syntax error score of the first error in each corrupted line.
+    points: u32,
+}
+
+impl LintSummary {
+    fn new(lints: &[LineLint]) -> Self {
+        LintSummary {
+            lines: lints.len(),
+            clean: lints.iter().filter(|l| l.is_clean()).count(),
+            corrupted: lints.iter().filter(|l| !l.errors.is_empty()).count(),
+            incomplete: lints
+                .iter()
+                .filter(|l| l.errors.is_empty() && l.unclosed > 0)
+                .count(),
+            errors: lints.iter().map(|l| l.errors.len()).sum(),
+            points: lints
+                .iter()
+                .filter_map(|l| l.errors.first())
+                .map(|e| e.points())
+                .sum(),
+        }
+    }
+}
+
+impl fmt::Display for LintSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} line(s): {} ok, {} corrupted with {} error(s) scoring {}, {} incomplete",
+            self.lines, self.clean, self.corrupted, self.errors, self.points, self.incomplete
+        )
+    }
+}
+
 struct Chunk {
     awaiting: char,
     closed: bool,
@@ -136,6 +286,10 @@ impl Chunk {
             }
         }
 
+        if !ILLEGAL_CHAR_POINTS.contains_key(&ch) {
+            return Err(ChunkError::InvalidChar(ch));
+        }
+
         if ch == self.awaiting {
             self.closed = true;
         } else {
@@ -151,9 +305,22 @@ impl ChunkVec {
         Self(vec![])
     }
 
+    // Parses a line, stopping at the first error.
+    fn parse(line: &str) -> Result<Self, ChunkError> {
+        let mut chunks = ChunkVec::new();
+        for ch in line.chars() {
+            chunks.consume(ch)?;
+        }
+
+        Ok(chunks)
+    }
+
     fn consume(&mut self, ch: char) -> Result<(), ChunkError> {
         match self.last_mut() {
             Some(chunk) if chunk.is_open() => chunk.consume(ch),
+            _ if ILLEGAL_CHAR_POINTS.contains_key(&ch) => {
+                Err(ChunkError::UnexpectedClosingChar(ch))
+            }
             _ => {
                 self.push(Chunk::open(ch)?);
                 Ok(())
@@ -161,12 +328,34 @@ impl ChunkVec {
         }
     }
 
-    fn is_complete(&self) -> bool {
-        if let Some(chunk) = self.last() {
-            return chunk.is_closed();
+    // The closing chars awaited by the open chunks, from the outermost to the innermost.
+    fn awaiting(&self) -> Vec<char> {
+        let mut awaiting = vec![];
+        let mut chunks = self;
+        while let Some(chunk) = chunks.last().filter(|c| c.is_open()) {
+            awaiting.push(chunk.awaiting);
+            chunks = &chunk.children;
         }
 
-        true
+        awaiting
+    }
+
+    // The closing chars that close every open chunk, innermost first.
+    fn completion(&self) -> String {
+        self.awaiting().into_iter().rev().collect()
+    }
+
+    // Closes the open chunks nested at the given depth or deeper.
+    fn close_from(&mut self, depth: usize) {
+        let mut chunks = self;
+        let mut d = 0;
+        while let Some(chunk) = chunks.last_mut().filter(|c| c.is_open()) {
+            if d >= depth {
+                chunk.closed = true;
+            }
+            chunks = &mut chunk.children;
+            d += 1;
+        }
     }
 }
 
@@ -194,4 +383,10 @@ enum ChunkError {
 
     #[error("expected closing char {0}")]
     ExpectedClosingChar(char),
+
+    #[error("invalid char {0}")]
+    InvalidChar(char),
+
+    #[error("unexpected closing char {0} with no open chunk")]
+    UnexpectedClosingChar(char),
 }